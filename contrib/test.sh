@@ -6,6 +6,7 @@ FEATURES="compiler serde"
 if [ -n "$TOOLCHAIN" ]
 then
     alias cargo="cargo +$TOOLCHAIN"
+    alias rustc="rustc +$TOOLCHAIN"
 fi
 
 # Lint if told to
@@ -26,6 +27,12 @@ do
     cargo test --verbose --features="$feature"
 done
 
+# Test the macros crate, which needs Rust 1.45
+if [ "$(rustc --version | cut -d' ' -f2 | cut -d. -f2)" -ge 45 ]
+then
+    cargo test --verbose -p miniscript-macros
+fi
+
 # Fuzz if told to
 if [ "$DO_FUZZ" = true ]
 then
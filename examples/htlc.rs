@@ -17,44 +17,53 @@
 extern crate bitcoin;
 extern crate miniscript;
 
+use bitcoin::hashes::sha256;
 use bitcoin::Network;
-use miniscript::policy::{Concrete, Liftable};
+use miniscript::policy::{templates, Liftable};
 use miniscript::Descriptor;
 use std::str::FromStr;
 
 fn main() {
     //HTLC policy with 10:1 odds for happy(co-operative) case compared to uncooperative case
-    let htlc_policy = Concrete::<bitcoin::PublicKey>::from_str(&format!("or(10@and(sha256({secret_hash}),pk({redeem_identity})),1@and(older({expiry}),pk({refund_identity})))",
-                                                  secret_hash = "1111111111111111111111111111111111111111111111111111111111111111",
-                                                  redeem_identity = "022222222222222222222222222222222222222222222222222222222222222222",
-                                                  refund_identity = "022222222222222222222222222222222222222222222222222222222222222222",
-                                                  expiry = "4444"
-    )).unwrap();
+    let htlc_policy = templates::htlc(
+        sha256::Hash::from_str("1111111111111111111111111111111111111111111111111111111111111111")
+            .unwrap(),
+        bitcoin::PublicKey::from_str(
+            "022222222222222222222222222222222222222222222222222222222222222222",
+        )
+        .unwrap(),
+        bitcoin::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap(),
+        4444,
+    )
+    .unwrap();
 
     let htlc_descriptor = Descriptor::Wsh(htlc_policy.compile().unwrap());
 
     assert_eq!(
         format!("{}", htlc_descriptor),
-        "wsh(andor(c:pk(022222222222222222222222222222222222222222222222222222222222222222),sha256(1111111111111111111111111111111111111111111111111111111111111111),and_v(vc:pk_h(4377a5acd66dc5cb67148a24818d1e51fa183bd2),older(4444))))"
+        "wsh(andor(c:pk(022222222222222222222222222222222222222222222222222222222222222222),sha256(1111111111111111111111111111111111111111111111111111111111111111),and_v(vc:pk_h(751e76e8199196d454941c45d1b3a323f1433bd6),older(4444))))"
     );
 
     assert_eq!(
         format!("{}", htlc_descriptor.lift()),
-        "or(and(pkh(4377a5acd66dc5cb67148a24818d1e51fa183bd2),and(pkh(4377a5acd66dc5cb67148a24818d1e51fa183bd2),older(4444))),sha256(1111111111111111111111111111111111111111111111111111111111111111))"
+        "or(and(pkh(4377a5acd66dc5cb67148a24818d1e51fa183bd2),sha256(1111111111111111111111111111111111111111111111111111111111111111)),and(pkh(751e76e8199196d454941c45d1b3a323f1433bd6),older(4444)))"
     );
 
     assert_eq!(
         format!("{:x}", htlc_descriptor.script_pubkey()),
-        "00203c0a59874cb570ff3093bcd67e846c967127c9e3fcd30f0a20857b504599e50a"
+        "00203feebb42bd21ee590f7cd56ce0ca7c0d36263764d78add18450e7dde16c14406"
     );
 
     assert_eq!(
         format!("{:x}", htlc_descriptor.witness_script()),
-        "21022222222222222222222222222222222222222222222222222222222222222222ac6476a9144377a5acd66dc5cb67148a24818d1e51fa183bd288ad025c11b26782012088a82011111111111111111111111111111111111111111111111111111111111111118768"
+        "21022222222222222222222222222222222222222222222222222222222222222222ac6476a914751e76e8199196d454941c45d1b3a323f1433bd688ad025c11b26782012088a82011111111111111111111111111111111111111111111111111111111111111118768"
     );
 
     assert_eq!(
         format!("{}", htlc_descriptor.address(Network::Bitcoin).unwrap()),
-        "bc1q8s99np6vk4c07vynhnt8aprvjecj0j0rlnfs7z3qs4a4q3veu59q8x3k8x"
+        "bc1q8lhtks4ay8h9jrmu64kwpjnup5mzvdmy679d6xz9pe7au9kpgsrqy4mp5r"
     );
 }
@@ -0,0 +1,289 @@
+// Miniscript
+// Written in 2020 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Miniscript Macros
+//!
+//! Macros which check Miniscript, descriptor and policy string literals
+//! when the calling crate is compiled, rather than when they are parsed.
+//!
+//! Keys are written as `format!` placeholders, `{}` or `{name}`, and are
+//! filled in at runtime from the remaining macro arguments. At compile time
+//! every placeholder is replaced by a dummy key and the result is parsed,
+//! so syntax errors, unknown fragments, typing errors and bad thresholds
+//! are reported as compile errors. Placeholders may only stand for keys or
+//! key hashes; hashes and timelocks must be written out in the literal.
+//!
+//! Each macro expands to a call to the corresponding `FromStr` impl of the
+//! `miniscript` crate, which the calling crate must depend on, and so
+//! evaluates to a `Result`. It can still fail at runtime if a key does not
+//! parse as the key type the result is used with.
+//!
+//! Unlike the `miniscript` crate, this crate needs Rust 1.45.0 or later.
+//!
+//! ```rust
+//! extern crate miniscript;
+//! #[macro_use]
+//! extern crate miniscript_macros;
+//!
+//! use miniscript::{Descriptor, Miniscript};
+//!
+//! fn main() {
+//!     let (a, b) = ("A", "B");
+//!     let ms: Miniscript<String> = miniscript!("or_d(c:pk({}),c:pk({}))", a, b).unwrap();
+//!     assert_eq!(ms.to_string(), "or_d(c:pk(A),c:pk(B))");
+//!
+//!     let desc: Descriptor<String> = descriptor!("wsh(thresh_m(2,{a},{b}))", a = a, b = b).unwrap();
+//!     assert_eq!(desc.to_string(), "wsh(thresh_m(2,A,B))");
+//! }
+//! ```
+//!
+//! Mistakes in the literal are caught by the compiler:
+//!
+//! ```rust,compile_fail
+//! extern crate miniscript;
+//! #[macro_use]
+//! extern crate miniscript_macros;
+//!
+//! fn main() {
+//!     // `or_d` needs a dissatisfiable left child
+//!     let ms: miniscript::Miniscript<String> =
+//!         miniscript!("or_d(v:pk({}),c:pk({}))", "A", "B").unwrap();
+//! }
+//! ```
+//!
+
+extern crate miniscript;
+extern crate proc_macro;
+
+use std::fmt;
+use std::str::FromStr;
+
+use miniscript::policy::concrete::PolicyError;
+use miniscript::policy::Concrete;
+use miniscript::{Descriptor, DummyKey, Error, Miniscript};
+use proc_macro::{TokenStream, TokenTree};
+
+/// Parse a Miniscript at compile time; expands to
+/// `Result<Miniscript<Pk>, miniscript::Error>`
+///
+/// ```rust
+/// extern crate miniscript;
+/// #[macro_use]
+/// extern crate miniscript_macros;
+///
+/// fn main() {
+///     let ms: miniscript::Miniscript<String> =
+///         miniscript!("and_v(vc:pk({}),older(144))", "A").unwrap();
+///     assert_eq!(ms.to_string(), "and_v(vc:pk(A),older(144))");
+/// }
+/// ```
+#[proc_macro]
+pub fn miniscript(input: TokenStream) -> TokenStream {
+    expand(input, "::miniscript::Miniscript", |s| {
+        Miniscript::<DummyKey>::from_str(s).map(|_| ())
+    })
+}
+
+/// Parse an output descriptor at compile time; expands to
+/// `Result<Descriptor<Pk>, miniscript::Error>`
+///
+/// ```rust
+/// extern crate miniscript;
+/// #[macro_use]
+/// extern crate miniscript_macros;
+///
+/// fn main() {
+///     let desc: miniscript::Descriptor<String> = descriptor!("sh(wpkh({}))", "A").unwrap();
+///     assert_eq!(desc.to_string(), "sh(wpkh(A))");
+/// }
+/// ```
+///
+/// ```rust,compile_fail
+/// extern crate miniscript;
+/// #[macro_use]
+/// extern crate miniscript_macros;
+///
+/// fn main() {
+///     // descriptors cannot be nested in `wpkh`
+///     let desc: miniscript::Descriptor<String> = descriptor!("wpkh(sh({}))", "A").unwrap();
+/// }
+/// ```
+#[proc_macro]
+pub fn descriptor(input: TokenStream) -> TokenStream {
+    expand(input, "::miniscript::Descriptor", |s| {
+        Descriptor::<DummyKey>::from_str(s).map(|_| ())
+    })
+}
+
+/// Parse a concrete policy at compile time and check it with
+/// `Concrete::check_policy`, except for duplicate keys since all
+/// placeholders are the same at compile time; expands to
+/// `Result<Concrete<Pk>, miniscript::Error>`
+///
+/// ```rust
+/// extern crate miniscript;
+/// #[macro_use]
+/// extern crate miniscript_macros;
+///
+/// fn main() {
+///     let policy: miniscript::policy::Concrete<String> =
+///         policy!("or(9@pk({}),1@and(pk({}),older(144)))", "A", "B").unwrap();
+///     assert_eq!(policy.to_string(), "or(9@pk(A),1@and(pk(B),older(144)))");
+/// }
+/// ```
+///
+/// ```rust,compile_fail
+/// extern crate miniscript;
+/// #[macro_use]
+/// extern crate miniscript_macros;
+///
+/// fn main() {
+///     // threshold larger than the number of subpolicies
+///     let policy: miniscript::policy::Concrete<String> =
+///         policy!("thresh(3,pk({}),pk({}))", "A", "B").unwrap();
+/// }
+/// ```
+#[proc_macro]
+pub fn policy(input: TokenStream) -> TokenStream {
+    expand(input, "::miniscript::policy::Concrete", |s| {
+        let policy = Concrete::<DummyKey>::from_str(s)?;
+        match policy.check_policy() {
+            Ok(()) | Err(PolicyError::DuplicatePubKeys) => Ok(()),
+            Err(e) => Err(Error::PolicyError(e)),
+        }
+    })
+}
+
+/// Check the string literal at the start of `input`, and expand to a call
+/// to `FromStr` of `ty` on the string formatted with the remaining arguments
+fn expand<F>(input: TokenStream, ty: &str, check: F) -> TokenStream
+where
+    F: FnOnce(&str) -> Result<(), Error>,
+{
+    let lit = match input.clone().into_iter().next() {
+        Some(TokenTree::Literal(lit)) => lit.to_string(),
+        _ => return compile_error("expected a string literal"),
+    };
+    let template = match unquote(&lit) {
+        Some(template) => template,
+        None => return compile_error("expected a string literal"),
+    };
+    let dummy = match strip_placeholders(&template) {
+        Some(dummy) => dummy,
+        None => return compile_error("unmatched brace in string literal"),
+    };
+    if let Err(e) = check(&dummy) {
+        return compile_error(e);
+    }
+    format!(
+        "<{}<_> as ::std::str::FromStr>::from_str(&format!({}))",
+        ty, input
+    )
+    .parse()
+    .expect("valid expansion")
+}
+
+/// A `compile_error!` invocation reporting `msg`
+fn compile_error<D: fmt::Display>(msg: D) -> TokenStream {
+    format!("compile_error!({:?})", msg.to_string())
+        .parse()
+        .expect("valid compile_error")
+}
+
+/// The contents of a string literal, as written in source code
+fn unquote(lit: &str) -> Option<String> {
+    if let Some(raw) = lit.strip_prefix('r') {
+        let hashes = raw.chars().take_while(|&c| c == '#').count();
+        if raw.len() < 2 * hashes + 2 {
+            return None;
+        }
+        return Some(raw[hashes + 1..raw.len() - hashes - 1].to_owned());
+    }
+    if lit.len() < 2 || !lit.starts_with('"') || !lit.ends_with('"') {
+        return None;
+    }
+    let mut ret = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            ret.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => ret.push('\\'),
+            Some('"') => ret.push('"'),
+            Some('\'') => ret.push('\''),
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('\n') => {
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(ret)
+}
+
+/// Replace every `format!` placeholder in `template` by the empty string,
+/// which parses as a `DummyKey`, and unescape doubled braces
+fn strip_placeholders(template: &str) -> Option<String> {
+    let mut ret = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                ret.push('{');
+            }
+            '{' => loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(_) => {}
+                    None => return None,
+                }
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                ret.push('}');
+            }
+            '}' => return None,
+            _ => ret.push(ch),
+        }
+    }
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(unquote("\"pk({})\""), Some("pk({})".to_owned()));
+        assert_eq!(unquote("r#\"pk({})\"#"), Some("pk({})".to_owned()));
+        assert_eq!(unquote("r\"pk({})\""), Some("pk({})".to_owned()));
+        assert_eq!(unquote("\"a\\\\b\""), Some("a\\b".to_owned()));
+        assert_eq!(unquote("b\"pk\""), None);
+        assert_eq!(unquote("1"), None);
+
+        assert_eq!(
+            strip_placeholders("thresh_m(2,{},{a},{0:})"),
+            Some("thresh_m(2,,,)".to_owned())
+        );
+        assert_eq!(strip_placeholders("{{}}"), Some("{}".to_owned()));
+        assert_eq!(strip_placeholders("pk({)"), None);
+        assert_eq!(strip_placeholders("pk(})"), None);
+    }
+}
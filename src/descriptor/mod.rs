@@ -31,6 +31,7 @@ use std::fmt;
 use std::str::{self, FromStr};
 
 use expression;
+use miniscript::satisfy::{MinWeight, SatisfactionStrategy, Timelocks};
 use miniscript::Miniscript;
 use Error;
 use MiniscriptKey;
@@ -212,27 +213,51 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
         txin: &mut bitcoin::TxIn,
         satisfier: S,
     ) -> Result<(), Error> {
-        fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
-            let mut b = script::Builder::new();
-            for wit in witness {
-                if let Ok(n) = script::read_scriptint(wit) {
-                    b = b.push_int(n);
-                } else {
-                    b = b.push_slice(wit);
-                }
-            }
-            b.into_script()
-        }
+        self.satisfy_with_strategy(txin, satisfier, &MinWeight)
+    }
+
+    /// Attempts to produce a satisfying witness and scriptSig to spend an
+    /// output controlled by the given descriptor, using `strategy` to choose
+    /// between alternative satisfactions; add the data to a given `TxIn`
+    /// output.
+    pub fn satisfy_with_strategy<S, St>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        satisfier: S,
+        strategy: &St,
+    ) -> Result<(), Error>
+    where
+        S: Satisfier<Pk>,
+        St: SatisfactionStrategy,
+    {
+        self.satisfy_with_timelocks(txin, satisfier, strategy)
+            .map(|_| ())
+    }
 
+    /// Attempts to produce a satisfying witness and scriptSig as
+    /// `satisfy_with_strategy` does, returning the timelocks used by the
+    /// chosen satisfaction. The input's `nSequence` and the transaction's
+    /// `nLockTime` must meet them for the spend to be valid; see
+    /// `Timelocks::apply`.
+    pub fn satisfy_with_timelocks<S, St>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        satisfier: S,
+        strategy: &St,
+    ) -> Result<Timelocks, Error>
+    where
+        S: Satisfier<Pk>,
+        St: SatisfactionStrategy,
+    {
         match *self {
             Descriptor::Bare(ref d) => {
-                let wit = match d.satisfy(satisfier) {
+                let (wit, timelocks) = match d.satisfy_with_timelocks(&satisfier, strategy) {
                     Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
+                    None => return Err(unsatisfied(d, &satisfier)),
                 };
                 txin.script_sig = witness_to_scriptsig(&wit);
                 txin.witness = vec![];
-                Ok(())
+                Ok(timelocks)
             }
             Descriptor::Pk(ref pk) => {
                 if let Some(sig) = satisfier.lookup_sig(pk) {
@@ -242,7 +267,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                         .push_slice(&sig_vec[..])
                         .into_script();
                     txin.witness = vec![];
-                    Ok(())
+                    Ok(Timelocks::default())
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
@@ -256,7 +281,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                         .push_key(&pk.to_public_key())
                         .into_script();
                     txin.witness = vec![];
-                    Ok(())
+                    Ok(Timelocks::default())
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
@@ -267,7 +292,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                     sig_vec.push(sig.1.as_u32() as u8);
                     txin.script_sig = Script::new();
                     txin.witness = vec![sig_vec, pk.to_public_key().to_bytes()];
-                    Ok(())
+                    Ok(Timelocks::default())
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
@@ -284,15 +309,124 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                         .push_slice(&redeem_script[..])
                         .into_script();
                     txin.witness = vec![sig_vec, pk.to_public_key().to_bytes()];
-                    Ok(())
+                    Ok(Timelocks::default())
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
             }
             Descriptor::Sh(ref d) => {
-                let mut witness = match d.satisfy(satisfier) {
+                let (mut witness, timelocks) = match d.satisfy_with_timelocks(&satisfier, strategy)
+                {
                     Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
+                    None => return Err(unsatisfied(d, &satisfier)),
+                };
+                witness.push(d.encode().into_bytes());
+                txin.script_sig = witness_to_scriptsig(&witness);
+                txin.witness = vec![];
+                Ok(timelocks)
+            }
+            Descriptor::Wsh(ref d) => {
+                let (mut witness, timelocks) = match d.satisfy_with_timelocks(&satisfier, strategy)
+                {
+                    Some(wit) => wit,
+                    None => return Err(unsatisfied(d, &satisfier)),
+                };
+                witness.push(d.encode().into_bytes());
+                txin.script_sig = Script::new();
+                txin.witness = witness;
+                Ok(timelocks)
+            }
+            Descriptor::ShWsh(ref d) => {
+                let witness_script = d.encode();
+                txin.script_sig = script::Builder::new()
+                    .push_slice(&witness_script.to_v0_p2wsh()[..])
+                    .into_script();
+
+                let (mut witness, timelocks) = match d.satisfy_with_timelocks(&satisfier, strategy)
+                {
+                    Some(wit) => wit,
+                    None => return Err(unsatisfied(d, &satisfier)),
+                };
+                witness.push(witness_script.into_bytes());
+                txin.witness = witness;
+                Ok(timelocks)
+            }
+        }
+    }
+
+    /// Attempts to satisfy input `index` of `tx`, which spends an output
+    /// controlled by the given descriptor, and sets the input's `nSequence`
+    /// and the transaction's `nLockTime` and version as required by the
+    /// chosen satisfaction. Fails if these conflict with the other inputs
+    /// of the transaction; see `Timelocks::apply`.
+    ///
+    /// Panics if `index` is out of range.
+    pub fn satisfy_input<S: Satisfier<Pk>>(
+        &self,
+        tx: &mut bitcoin::Transaction,
+        index: usize,
+        satisfier: S,
+    ) -> Result<(), Error> {
+        let mut txin = tx.input[index].clone();
+        let timelocks = self.satisfy_with_timelocks(&mut txin, satisfier, &MinWeight)?;
+        timelocks.apply(tx, index)?;
+        tx.input[index].script_sig = txin.script_sig;
+        tx.input[index].witness = txin.witness;
+        Ok(())
+    }
+
+    /// Attempts to produce a dissatisfying witness and scriptSig for an
+    /// output controlled by the given descriptor; add the data to a given
+    /// `TxIn` output. A dissatisfaction causes the script to fail, so the
+    /// result is never a valid spend; it is useful for testing and for
+    /// protocols which need to check that a dissatisfaction exists.
+    pub fn dissatisfy<S: Satisfier<Pk>>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        satisfier: S,
+    ) -> Result<(), Error> {
+        match *self {
+            Descriptor::Bare(ref d) => {
+                let wit = match d.dissatisfy(satisfier) {
+                    Some(wit) => wit,
+                    None => return Err(Error::CouldNotDissatisfy),
+                };
+                txin.script_sig = witness_to_scriptsig(&wit);
+                txin.witness = vec![];
+                Ok(())
+            }
+            Descriptor::Pk(..) => {
+                txin.script_sig = script::Builder::new().push_int(0).into_script();
+                txin.witness = vec![];
+                Ok(())
+            }
+            Descriptor::Pkh(ref pk) => {
+                txin.script_sig = script::Builder::new()
+                    .push_int(0)
+                    .push_key(&pk.to_public_key())
+                    .into_script();
+                txin.witness = vec![];
+                Ok(())
+            }
+            Descriptor::Wpkh(ref pk) => {
+                txin.script_sig = Script::new();
+                txin.witness = vec![vec![], pk.to_public_key().to_bytes()];
+                Ok(())
+            }
+            Descriptor::ShWpkh(ref pk) => {
+                let addr = bitcoin::Address::p2wpkh(&pk.to_public_key(), bitcoin::Network::Bitcoin);
+                let redeem_script = addr.script_pubkey();
+
+                txin.script_sig = script::Builder::new()
+                    .push_slice(&redeem_script[..])
+                    .into_script();
+                txin.witness = vec![vec![], pk.to_public_key().to_bytes()];
+                Ok(())
+            }
+            Descriptor::Sh(ref d) => {
+                let mut witness = match d.dissatisfy(satisfier) {
+                    Some(wit) => wit,
+                    None => return Err(Error::CouldNotDissatisfy),
                 };
                 witness.push(d.encode().into_bytes());
                 txin.script_sig = witness_to_scriptsig(&witness);
@@ -300,9 +434,9 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                 Ok(())
             }
             Descriptor::Wsh(ref d) => {
-                let mut witness = match d.satisfy(satisfier) {
+                let mut witness = match d.dissatisfy(satisfier) {
                     Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
+                    None => return Err(Error::CouldNotDissatisfy),
                 };
                 witness.push(d.encode().into_bytes());
                 txin.script_sig = Script::new();
@@ -315,9 +449,9 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                     .push_slice(&witness_script.to_v0_p2wsh()[..])
                     .into_script();
 
-                let mut witness = match d.satisfy(satisfier) {
+                let mut witness = match d.dissatisfy(satisfier) {
                     Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
+                    None => return Err(Error::CouldNotDissatisfy),
                 };
                 witness.push(witness_script.into_bytes());
                 txin.witness = witness;
@@ -326,6 +460,21 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
         }
     }
 
+    /// Whether the descriptor has a unique dissatisfaction which is always
+    /// available, i.e. one that cannot be malleated by a third party.
+    pub fn has_nonmalleable_dissatisfaction(&self) -> bool {
+        match *self {
+            Descriptor::Pk(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::ShWpkh(..) => true,
+            Descriptor::Bare(ref ms)
+            | Descriptor::Sh(ref ms)
+            | Descriptor::Wsh(ref ms)
+            | Descriptor::ShWsh(ref ms) => ms.has_nonmalleable_dissatisfaction(),
+        }
+    }
+
     /// Computes an upper bound on the weight of a satisfying witness to the
     /// transaction. Assumes all signatures are 73 bytes, including push opcode
     /// and sighash suffix. Includes the weight of the VarInts encoding the
@@ -377,6 +526,96 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             }
         }
     }
+
+    /// Computes an upper bound on the weight of a dissatisfying witness to
+    /// the transaction, or `None` if the descriptor cannot be dissatisfied.
+    /// Includes the weight of the VarInts encoding the scriptSig and witness
+    /// stack length.
+    pub fn max_dissatisfaction_weight(&self) -> Option<usize> {
+        fn varint_len(n: usize) -> usize {
+            bitcoin::VarInt(n as u64).len()
+        }
+
+        match *self {
+            Descriptor::Bare(ref ms) => {
+                let scriptsig_len = ms.max_dissatisfaction_size(1)?;
+                Some(4 * (varint_len(scriptsig_len) + scriptsig_len))
+            }
+            Descriptor::Pk(..) => Some(4 * (1 + 1)),
+            Descriptor::Pkh(ref pk) => Some(4 * (1 + 1 + pk.serialized_len())),
+            Descriptor::Wpkh(ref pk) => Some(4 + 1 + 1 + pk.serialized_len()),
+            Descriptor::ShWpkh(ref pk) => Some(4 * 24 + 1 + 1 + pk.serialized_len()),
+            Descriptor::Sh(ref ms) => {
+                let ss = ms.script_size();
+                let push_size = if ss < 76 {
+                    1
+                } else if ss < 0x100 {
+                    2
+                } else if ss < 0x10000 {
+                    3
+                } else {
+                    5
+                };
+
+                let scriptsig_len = push_size + ss + ms.max_dissatisfaction_size(1)?;
+                Some(4 * (varint_len(scriptsig_len) + scriptsig_len))
+            }
+            Descriptor::Wsh(ref ms) => {
+                let script_size = ms.script_size();
+                Some(
+                    4 +  // scriptSig length byte
+                    varint_len(script_size) +
+                    script_size +
+                    varint_len(ms.max_dissatisfaction_witness_elements()?) +
+                    ms.max_dissatisfaction_size(2)?,
+                )
+            }
+            Descriptor::ShWsh(ref ms) => {
+                let script_size = ms.script_size();
+                Some(
+                    4 * 36
+                        + varint_len(script_size)
+                        + script_size
+                        + varint_len(ms.max_dissatisfaction_witness_elements()?)
+                        + ms.max_dissatisfaction_size(2)?,
+                )
+            }
+        }
+    }
+}
+
+/// Maximum number of alternative sets of missing items reported when a
+/// descriptor cannot be satisfied
+const MAX_MISSING_SETS: usize = 1000;
+
+/// Describes why a Miniscript could not be satisfied, listing the missing
+/// items if there are any
+fn unsatisfied<Pk, S>(ms: &Miniscript<Pk>, satisfier: S) -> Error
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    S: Satisfier<Pk>,
+{
+    let sets = match ms.missing_items(satisfier, MAX_MISSING_SETS) {
+        Ok(sets) => sets,
+        Err(_) => return Error::CouldNotSatisfy,
+    };
+    match sets.first() {
+        Some(set) if !set.is_empty() => Error::MissingItems(sets),
+        _ => Error::CouldNotSatisfy,
+    }
+}
+
+/// Convert a witness stack into a scriptSig which pushes the same elements
+fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
+    let mut b = script::Builder::new();
+    for wit in witness {
+        if let Ok(n) = script::read_scriptint(wit) {
+            b = b.push_int(n);
+        } else {
+            b = b.push_slice(wit);
+        }
+    }
+    b.into_script()
 }
 
 impl<Pk> expression::FromTree for Descriptor<Pk>
@@ -534,15 +773,17 @@ mod tests {
     use bitcoin::hashes::hex::FromHex;
     use bitcoin::hashes::{hash160, sha256};
     use bitcoin::{self, secp256k1, PublicKey};
-    use miniscript::satisfy::BitcoinSig;
+    use miniscript::satisfy::{After, BitcoinSig, MinWeight, Missing, Older};
+    use std::collections::HashMap;
     use std::str::FromStr;
     use Descriptor;
+    use Error;
     use Miniscript;
+    use RelLockTime;
     use Satisfier;
 
     type StdDescriptor = Descriptor<PublicKey>;
-    const TEST_PK: &'static str =
-        "pk(\
+    const TEST_PK: &'static str = "pk(\
          020000000000000000000000000000000000000000000000000000000000000002\
          )";
 
@@ -877,6 +1118,195 @@ mod tests {
         );
     }
 
+    #[test]
+    fn dissatisfy() {
+        let pk = bitcoin::PublicKey::from_str(
+            "020000000000000000000000000000000000000000000000000000000000000002",
+        )
+        .unwrap();
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 100,
+            witness: vec![],
+        };
+
+        let wpkh = Descriptor::Wpkh(pk);
+        wpkh.dissatisfy(&mut txin, ()).expect("dissatisfaction");
+        assert_eq!(txin.witness, vec![vec![], pk.to_bytes()]);
+        assert!(wpkh.has_nonmalleable_dissatisfaction());
+        assert_eq!(wpkh.max_dissatisfaction_weight(), Some(4 + 1 + 1 + 34));
+
+        let ms: Miniscript<PublicKey> = ms_str!("or_d(c:pk({}),c:pk({}))", pk, pk);
+        let wsh = Descriptor::Wsh(ms.clone());
+        wsh.dissatisfy(&mut txin, ()).expect("dissatisfaction");
+        assert_eq!(txin.script_sig, bitcoin::Script::new());
+        assert_eq!(txin.witness, vec![vec![], vec![], ms.encode().into_bytes()]);
+        assert!(wsh.has_nonmalleable_dissatisfaction());
+
+        // `or_i` with only one dissatisfiable branch
+        let ms: Miniscript<PublicKey> =
+            ms_str!("or_i(c:pk({}),and_v(v:older(10),c:pk({})))", pk, pk);
+        assert_eq!(ms.dissatisfy(()), Some(vec![vec![], vec![1]]));
+        assert_eq!(ms.max_dissatisfaction_size(2), Some(3));
+
+        // `older` cannot be dissatisfied at all
+        let wsh = StdDescriptor::from_str("wsh(older(10))").unwrap();
+        assert!(!wsh.has_nonmalleable_dissatisfaction());
+        assert_eq!(wsh.max_dissatisfaction_weight(), None);
+        match wsh.dissatisfy(&mut txin, ()) {
+            Err(Error::CouldNotDissatisfy) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn satisfy_missing_items() {
+        let secp = secp256k1::Secp256k1::new();
+        let mut pks = vec![];
+        for i in 1..5 {
+            let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+            pks.push(bitcoin::PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                compressed: true,
+            });
+        }
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+
+        let mut sigs = HashMap::new();
+        sigs.insert(pks[0], sig);
+
+        let desc: StdDescriptor = des_str!(
+            "wsh(or_d(thresh_m(2,{},{},{}),and_v(vc:pk({}),older(10))))",
+            pks[0],
+            pks[1],
+            pks[2],
+            pks[3]
+        );
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 100,
+            witness: vec![],
+        };
+
+        let mut expected = vec![vec![Missing::Sig(pks[1])], vec![Missing::Sig(pks[2])]];
+        expected.sort();
+        let mut last = vec![
+            Missing::Sig(pks[3]),
+            Missing::Older(RelLockTime::from_consensus(10).unwrap()),
+        ];
+        last.sort();
+        expected.push(last);
+        match desc.satisfy(&mut txin, &sigs) {
+            Err(Error::MissingItems(sets)) => assert_eq!(sets, expected),
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // With the timelock available only the signature is missing
+        sigs.insert(pks[1], sig);
+        match desc {
+            Descriptor::Wsh(ref ms) => {
+                assert_eq!(ms.missing_items(&sigs, 10).unwrap(), vec![vec![]]);
+                sigs.remove(&pks[1]);
+                assert_eq!(
+                    ms.missing_items((&sigs, Older(10)), 10).unwrap()[0],
+                    vec![Missing::Sig(pks[3])]
+                );
+                // The three alternatives exceed a limit of two sets
+                match ms.missing_items(&sigs, 2) {
+                    Err(Error::DnfTooLarge(2)) => {}
+                    x => panic!("unexpected result {:?}", x),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn satisfy_input_timelocks() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+        let mut sigs = HashMap::new();
+        sigs.insert(pk, sig);
+
+        let txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let mut tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+
+        // A relative timelock sets the sequence and bumps the version
+        let older: StdDescriptor = des_str!("wsh(and_v(vc:pk({}),older(144)))", pk);
+        let mut txin = tx.input[0].clone();
+        let timelocks = older
+            .satisfy_with_timelocks(&mut txin, (&sigs, Older(144)), &MinWeight)
+            .unwrap();
+        assert_eq!(
+            timelocks.older,
+            Some(RelLockTime::from_height(144).unwrap())
+        );
+        assert_eq!(timelocks.after, None);
+        older
+            .satisfy_input(&mut tx, 0, (&sigs, Older(144)))
+            .unwrap();
+        assert_eq!(tx.input[0].sequence, 144);
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, 0);
+        assert_eq!(tx.input[0].witness, txin.witness);
+
+        // Input 0 is now signed, so the locktime can no longer change
+        let after: StdDescriptor = des_str!("wsh(and_v(vc:pk({}),after(600000)))", pk);
+        match after.satisfy_input(&mut tx, 1, (&sigs, After(600000))) {
+            Err(Error::InputAlreadySatisfied(0)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        tx.input[0].witness = vec![];
+        after
+            .satisfy_input(&mut tx, 1, (&sigs, After(600000)))
+            .unwrap();
+        assert_eq!(tx.lock_time, 600000);
+        assert_eq!(tx.input[1].sequence, 0xfffffffe);
+
+        // A timestamp cannot be combined with a height
+        let after_time: StdDescriptor = des_str!("wsh(and_v(vc:pk({}),after(1500000000)))", pk);
+        match after_time.satisfy_input(&mut tx, 0, (&sigs, After(1500000000))) {
+            Err(Error::LockTimeConflict(1500000000, 600000)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        assert_eq!(tx.lock_time, 600000);
+        assert_eq!(tx.input[0].sequence, 144);
+
+        // Input 1 is now signed, so the sequence of input 0 can no longer
+        // change either
+        let older_288: StdDescriptor = des_str!("wsh(and_v(vc:pk({}),older(288)))", pk);
+        match older_288.satisfy_input(&mut tx, 0, (&sigs, Older(288))) {
+            Err(Error::InputAlreadySatisfied(1)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        assert_eq!(tx.input[0].sequence, 144);
+        tx.input[1].witness = vec![];
+        older_288
+            .satisfy_input(&mut tx, 0, (&sigs, Older(288)))
+            .unwrap();
+        assert_eq!(tx.input[0].sequence, 288);
+    }
+
     #[test]
     fn after_is_cltv() {
         let descriptor = Descriptor::<bitcoin::PublicKey>::from_str("wsh(after(1000))").unwrap();
@@ -18,7 +18,7 @@ use fmt;
 use Descriptor;
 use Terminal;
 use {error, Miniscript};
-use {BitcoinSig, ToPublicKey};
+use {AbsLockTime, BitcoinSig, RelLockTime, ToPublicKey};
 
 /// Detailed Error type for Interpreter
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -189,9 +189,9 @@ pub enum SatisfiedConstraint<'desc, 'stack> {
         preimage: &'stack [u8],
     },
     ///Relative Timelock for CSV.
-    RelativeTimeLock { time: &'desc u32 },
+    RelativeTimeLock { time: &'desc RelLockTime },
     ///Absolute Timelock for CLTV.
-    AbsoluteTimeLock { time: &'desc u32 },
+    AbsoluteTimeLock { time: &'desc AbsLockTime },
 }
 
 ///This is used by the interpreter to know which evaluation state a AstemElem is.
@@ -275,6 +275,9 @@ where
     /// descriptor by a given witness stack. Because this iterator is lazy,
     /// it may return satisfied constraints even if these turn out to be
     /// irrelevant to the final (dis)satisfaction of the descriptor.
+    /// `age` is the `nSequence` of the spending input and `height` is the
+    /// `nLockTime` of the spending transaction; they are checked against
+    /// `older` and `after` fragments respectively.
     pub fn from_descriptor(
         des: &'desc Descriptor<bitcoin::PublicKey>,
         stack: Stack<'stack>,
@@ -348,7 +351,7 @@ where
                 Terminal::After(ref n) => {
                     debug_assert_eq!(node_state.n_evaluated, 0);
                     debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_after(n, self.age);
+                    let res = self.stack.evaluate_after(n, self.height);
                     if res.is_some() {
                         return res;
                     }
@@ -356,7 +359,7 @@ where
                 Terminal::Older(ref n) => {
                     debug_assert_eq!(node_state.n_evaluated, 0);
                     debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_older(n, self.height);
+                    let res = self.stack.evaluate_older(n, self.age);
                     if res.is_some() {
                         return res;
                     }
@@ -842,40 +845,40 @@ impl<'stack> Stack<'stack> {
     }
 
     /// Helper function to evaluate a After Node. Takes no argument from stack
-    /// `n CHECKSEQUENCEVERIFY 0NOTEQUAL` and `n CHECKSEQUENCEVERIFY`
+    /// `n CHECKLOCKTIMEVERIFY 0NOTEQUAL` and `n CHECKLOCKTIMEVERIFY`
     /// Ideally this should return int value as n: build_scriptint(t as i64)),
     /// The reason we don't need to copy the Script semantics is that
     /// Miniscript never evaluates integers and it is safe to treat them as
     /// booleans
     fn evaluate_after<'desc>(
         &mut self,
-        n: &'desc u32,
-        age: u32,
+        n: &'desc AbsLockTime,
+        height: u32,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>> {
-        if age >= *n {
+        if n.is_satisfied_by(height) {
             self.push(StackElement::Satisfied);
-            Some(Ok(SatisfiedConstraint::RelativeTimeLock { time: n }))
+            Some(Ok(SatisfiedConstraint::AbsoluteTimeLock { time: n }))
         } else {
-            Some(Err(Error::AbsoluteLocktimeNotMet(*n)))
+            Some(Err(Error::AbsoluteLocktimeNotMet(n.to_consensus_u32())))
         }
     }
 
     /// Helper function to evaluate a Older Node. Takes no argument from stack
-    /// `n CHECKLOCKTIMEVERIFY 0NOTEQUAL` and `n CHECKLOCKTIMEVERIFY`
+    /// `n CHECKSEQUENCEVERIFY 0NOTEQUAL` and `n CHECKSEQUENCEVERIFY`
     /// Ideally this should return int value as n: build_scriptint(t as i64)),
     /// The reason we don't need to copy the Script semantics is that
     /// Miniscript never evaluates integers and it is safe to treat them as
     /// booleans
     fn evaluate_older<'desc>(
         &mut self,
-        n: &'desc u32,
-        height: u32,
+        n: &'desc RelLockTime,
+        age: u32,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>> {
-        if height >= *n {
+        if n.is_satisfied_by(age) {
             self.push(StackElement::Satisfied);
-            Some(Ok(SatisfiedConstraint::AbsoluteTimeLock { time: n }))
+            Some(Ok(SatisfiedConstraint::RelativeTimeLock { time: n }))
         } else {
-            Some(Err(Error::RelativeLocktimeNotMet(*n)))
+            Some(Err(Error::RelativeLocktimeNotMet(n.to_consensus_u32())))
         }
     }
 
@@ -1022,11 +1025,14 @@ mod tests {
         Error, HashLockType, NodeEvaluationState, SatisfiedConstraint, SatisfiedConstraints, Stack,
         StackElement,
     };
+    use std::collections::HashMap;
     use std::str::FromStr;
     use BitcoinSig;
+    use Descriptor;
     use Miniscript;
     use MiniscriptKey;
     use ToPublicKey;
+    use {AbsLockTime, RelLockTime};
 
     fn setup_keys_sigs(
         n: usize,
@@ -1153,7 +1159,9 @@ mod tests {
         let after_satisfied: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert_eq!(
             after_satisfied.unwrap(),
-            vec![SatisfiedConstraint::RelativeTimeLock { time: &1000 }]
+            vec![SatisfiedConstraint::AbsoluteTimeLock {
+                time: &AbsLockTime::from_consensus(1000).unwrap()
+            }]
         );
 
         //Check Older
@@ -1162,7 +1170,9 @@ mod tests {
         let older_satisfied: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert_eq!(
             older_satisfied.unwrap(),
-            vec![SatisfiedConstraint::AbsoluteTimeLock { time: &1000 }]
+            vec![SatisfiedConstraint::RelativeTimeLock {
+                time: &RelLockTime::from_consensus(1000).unwrap()
+            }]
         );
 
         //Check Sha256
@@ -1471,4 +1481,39 @@ mod tests {
         let thresh_m_error: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert!(thresh_m_error.is_err());
     }
+
+    #[test]
+    fn satisfy_and_interpret() {
+        let (pks, der_sigs, secp_sigs, sighash, secp) = setup_keys_sigs(3);
+        let vfyfn =
+            |pk: &bitcoin::PublicKey, (sig, _)| secp.verify(&sighash, &sig, &pk.key).is_ok();
+
+        // No signature for the last key, so andor must use its first branch
+        let mut sigs = HashMap::new();
+        for i in 0..2 {
+            sigs.insert(pks[i], (secp_sigs[i], bitcoin::SigHashType::All));
+        }
+
+        // The left child is executed first, so its witness must be on top
+        for ms_str in &[
+            format!("and_v(vc:pk({}),c:pk({}))", pks[0], pks[1]),
+            format!("and_b(c:pk({}),sc:pk({}))", pks[0], pks[1]),
+            format!("andor(c:pk({}),c:pk({}),c:pk({}))", pks[0], pks[1], pks[2]),
+        ] {
+            let ms = Miniscript::<bitcoin::PublicKey>::from_str(ms_str).unwrap();
+            let witness = ms.satisfy(&sigs).unwrap();
+            assert_eq!(witness.len(), 2);
+            assert_eq!(witness[1], der_sigs[0]);
+
+            let des = Descriptor::Wsh(ms);
+            let elems = witness
+                .iter()
+                .map(|elem| StackElement::from(elem))
+                .collect();
+            let stack = Stack(elems);
+            let constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 0, 0);
+            let satisfied: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
+            assert_eq!(satisfied.unwrap().len(), 2);
+        }
+    }
 }
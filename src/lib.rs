@@ -105,7 +105,8 @@ use bitcoin::hashes::{hash160, sha256, Hash};
 
 pub use descriptor::{Descriptor, SatisfiedConstraints};
 pub use miniscript::decode::Terminal;
-pub use miniscript::satisfy::{BitcoinSig, Satisfier};
+pub use miniscript::satisfy::{BitcoinSig, Satisfier, SatisfierBuilder};
+pub use miniscript::timelock::{AbsLockTime, RelLockTime};
 pub use miniscript::Miniscript;
 
 ///Public key trait which can be converted to Hash type
@@ -116,11 +117,21 @@ pub trait MiniscriptKey:
 
     ///Converts an object to PublicHash
     fn to_pubkeyhash(&self) -> Self::Hash;
+
+    /// Whether the key is an uncompressed public key, which segwit outputs
+    /// do not allow. Key types which cannot tell return `false`.
+    fn is_uncompressed(&self) -> bool {
+        false
+    }
 }
 
 impl MiniscriptKey for bitcoin::PublicKey {
     type Hash = hash160::Hash;
 
+    fn is_uncompressed(&self) -> bool {
+        !self.compressed
+    }
+
     fn to_pubkeyhash(&self) -> Self::Hash {
         let mut engine = hash160::Hash::engine();
         self.write_into(&mut engine);
@@ -296,8 +307,26 @@ pub enum Error {
     RelativeLocktimeNotMet(u32),
     /// Could not satisfy, absolute locktime not met
     AbsoluteLocktimeNotMet(u32),
+    /// Relative locktime was zero or had the BIP 68 disable flag set
+    InvalidRelativeLockTime(u32),
+    /// Absolute locktime was zero
+    InvalidAbsoluteLockTime(u32),
+    /// A required absolute locktime (first) is a height while the
+    /// transaction's locktime (second) is a time, or vice-versa
+    LockTimeConflict(u32, u32),
+    /// Setting timelocks would invalidate the already-satisfied input with
+    /// the given index
+    InputAlreadySatisfied(usize),
+    /// The disjunctive normal form of a policy has more than the given
+    /// number of terms
+    DnfTooLarge(usize),
     /// General failure to satisfy
     CouldNotSatisfy,
+    /// Could not satisfy; any one of the listed sets of missing items would
+    /// have made satisfaction possible
+    MissingItems(Vec<Vec<miniscript::satisfy::Missing>>),
+    /// General failure to dissatisfy
+    CouldNotDissatisfy,
     /// Typechecking failed
     TypeCheck(String),
     ///General error in creating descriptor
@@ -309,6 +338,8 @@ pub enum Error {
     CompilerError(policy::compiler::CompilerError),
     ///Interpreter related errors
     InterpreterError(descriptor::InterpreterError),
+    /// Errors related to concrete policies
+    PolicyError(policy::concrete::PolicyError),
     /// Bad Script Sig. As per standardness rules, only pushes are allowed in
     /// scriptSig. This error is invoked when op_codes are pushed onto the stack
     /// As per the current implementation, pushing an integer apart from 0 or 1
@@ -389,12 +420,46 @@ impl fmt::Display for Error {
                 "required absolute locktime CLTV of {} blocks, not met",
                 n
             ),
+            Error::InvalidRelativeLockTime(n) => write!(f, "invalid relative locktime {}", n),
+            Error::InvalidAbsoluteLockTime(n) => write!(f, "invalid absolute locktime {}", n),
+            Error::LockTimeConflict(n, lock_time) => write!(
+                f,
+                "absolute locktime {} conflicts with transaction locktime {}",
+                n, lock_time
+            ),
+            Error::InputAlreadySatisfied(i) => {
+                write!(f, "changing timelocks would invalidate input {}", i)
+            }
+            Error::DnfTooLarge(n) => write!(
+                f,
+                "disjunctive normal form of policy has more than {} terms",
+                n
+            ),
             Error::CouldNotSatisfy => f.write_str("could not satisfy"),
+            Error::MissingItems(ref sets) => {
+                f.write_str("could not satisfy, missing one of: ")?;
+                for (i, set) in sets.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("; ")?;
+                    }
+                    f.write_str("{")?;
+                    for (j, item) in set.iter().enumerate() {
+                        if j > 0 {
+                            f.write_str(", ")?;
+                        }
+                        write!(f, "{}", item)?;
+                    }
+                    f.write_str("}")?;
+                }
+                Ok(())
+            }
+            Error::CouldNotDissatisfy => f.write_str("could not dissatisfy"),
             Error::BadPubkey(ref e) => fmt::Display::fmt(e, f),
             Error::TypeCheck(ref e) => write!(f, "typecheck: {}", e),
             Error::BadDescriptor => f.write_str("could not create a descriptor"),
             Error::Secp(ref e) => fmt::Display::fmt(e, f),
             Error::InterpreterError(ref e) => fmt::Display::fmt(e, f),
+            Error::PolicyError(ref e) => fmt::Display::fmt(e, f),
             #[cfg(feature = "compiler")]
             Error::CompilerError(ref e) => fmt::Display::fmt(e, f),
             Error::BadScriptSig => f.write_str("Script sig must only consist of pushes"),
@@ -425,6 +490,13 @@ impl From<policy::compiler::CompilerError> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<policy::concrete::PolicyError> for Error {
+    fn from(e: policy::concrete::PolicyError) -> Error {
+        Error::PolicyError(e)
+    }
+}
+
 /// The size of an encoding of a number in Script
 pub fn script_num_size(n: usize) -> usize {
     match n {
@@ -27,6 +27,7 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 
 use errstr;
 use expression;
+use miniscript::timelock::{AbsLockTime, RelLockTime};
 use miniscript::types::{self, Property};
 use script_num_size;
 use std::sync::Arc;
@@ -357,10 +358,14 @@ where
                 expression::terminal(&top.args[0], |x| Pk::Hash::from_str(x).map(Terminal::PkH))
             }
             ("after", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Terminal::After)
+                expression::parse_num(x)
+                    .and_then(AbsLockTime::from_consensus)
+                    .map(Terminal::After)
             }),
             ("older", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Terminal::Older)
+                expression::parse_num(x)
+                    .and_then(RelLockTime::from_consensus)
+                    .map(Terminal::Older)
             }),
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256::Hash::from_hex(x).map(Terminal::Sha256)
@@ -509,9 +514,11 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
                 .push_slice(&Pk::hash_to_hash160(&hash)[..])
                 .push_opcode(opcodes::all::OP_EQUALVERIFY),
             Terminal::After(t) => builder
-                .push_int(t as i64)
+                .push_int(t.to_consensus_u32() as i64)
                 .push_opcode(opcodes::all::OP_CLTV),
-            Terminal::Older(t) => builder.push_int(t as i64).push_opcode(opcodes::all::OP_CSV),
+            Terminal::Older(t) => builder
+                .push_int(t.to_consensus_u32() as i64)
+                .push_opcode(opcodes::all::OP_CSV),
             Terminal::Sha256(h) => builder
                 .push_opcode(opcodes::all::OP_SIZE)
                 .push_int(32)
@@ -630,8 +637,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
         match *self {
             Terminal::Pk(ref pk) => pk.serialized_len(),
             Terminal::PkH(..) => 24,
-            Terminal::After(n) => script_num_size(n as usize) + 1,
-            Terminal::Older(n) => script_num_size(n as usize) + 1,
+            Terminal::After(n) => script_num_size(n.to_consensus_u32() as usize) + 1,
+            Terminal::Older(n) => script_num_size(n.to_consensus_u32() as usize) + 1,
             Terminal::Sha256(..) => 33 + 6,
             Terminal::Hash256(..) => 33 + 6,
             Terminal::Ripemd160(..) => 21 + 6,
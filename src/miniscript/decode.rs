@@ -21,6 +21,7 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use {bitcoin, Miniscript};
 
 use miniscript::lex::{Token as Tk, TokenIter};
+use miniscript::timelock::{AbsLockTime, RelLockTime};
 use miniscript::types::extra_props::ExtData;
 use miniscript::types::Property;
 use miniscript::types::Type;
@@ -71,10 +72,10 @@ pub enum Terminal<Pk: MiniscriptKey> {
     /// `DUP HASH160 <keyhash> EQUALVERIFY`
     PkH(Pk::Hash),
     // timelocks
-    /// `n CHECKSEQUENCEVERIFY`
-    After(u32),
     /// `n CHECKLOCKTIMEVERIFY`
-    Older(u32),
+    After(AbsLockTime),
+    /// `n CHECKSEQUENCEVERIFY`
+    Older(RelLockTime),
     // hashlocks
     /// `SIZE 32 EQUALVERIFY SHA256 <hash> EQUAL`
     Sha256(sha256::Hash),
@@ -240,9 +241,9 @@ pub fn parse(tokens: &mut TokenIter) -> Result<Miniscript<bitcoin::PublicKey>, E
                     },
                     // timelocks
                     Tk::CheckSequenceVerify, Tk::Num(n)
-                        => term.reduce0(Terminal::Older(n))?,
+                        => term.reduce0(Terminal::Older(RelLockTime::from_consensus(n)?))?,
                     Tk::CheckLockTimeVerify, Tk::Num(n)
-                        => term.reduce0(Terminal::After(n))?,
+                        => term.reduce0(Terminal::After(AbsLockTime::from_consensus(n)?))?,
                     // hashlocks
                     Tk::Equal => match_token!(
                         tokens,
@@ -112,6 +112,20 @@ impl Iterator for TokenIter {
     }
 }
 
+/// Decode a script number of up to 5 bytes, the size accepted by
+/// `OP_CHECKLOCKTIMEVERIFY`; `script::read_scriptint` only accepts 4
+fn read_scriptint(bytes: &[u8]) -> Result<i64, script::Error> {
+    if bytes.len() != 5 {
+        return script::read_scriptint(bytes);
+    }
+    let mut ret = bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as i64);
+    if bytes[4] & 0x80 != 0 {
+        ret &= (1 << 39) - 1;
+        ret = -ret;
+    }
+    Ok(ret)
+}
+
 /// Tokenize a script
 pub fn lex(script: &script::Script) -> Result<Vec<Token>, Error> {
     let mut ret = Vec::with_capacity(script.len());
@@ -230,8 +244,9 @@ pub fn lex(script: &script::Script) -> Result<Vec<Token>, Error> {
                         ));
                     }
                     _ => {
-                        match script::read_scriptint(bytes) {
-                            Ok(v) if v >= 0 => {
+                        match read_scriptint(bytes) {
+                            // non-negative and fits in a `u32`
+                            Ok(v) if v >> 32 == 0 => {
                                 // check minimality of the number
                                 if &script::Builder::new().push_int(v).into_script()[1..] != bytes {
                                     return Err(Error::InvalidPush(bytes.to_owned()));
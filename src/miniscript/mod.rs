@@ -35,6 +35,7 @@ pub mod astelem;
 pub mod decode;
 pub mod lex;
 pub mod satisfy;
+pub mod timelock;
 pub mod types;
 
 use self::lex::{lex, TokenIter};
@@ -192,6 +193,26 @@ impl<Pk: MiniscriptKey + ToPublicKey> Miniscript<Pk> {
     pub fn max_satisfaction_size(&self, one_cost: usize) -> usize {
         self.node.max_satisfaction_size(one_cost)
     }
+
+    /// Maximum size, in bytes, of a dissatisfying witness, or `None` if the
+    /// Miniscript cannot be dissatisfied. `one_cost` has the same meaning
+    /// as for `max_satisfaction_size`.
+    ///
+    /// In general, it is not recommended to use this function directly, but
+    /// to instead call the corresponding function on a `Descriptor`, which
+    /// will handle the segwit/non-segwit technicalities for you.
+    pub fn max_dissatisfaction_size(&self, one_cost: usize) -> Option<usize> {
+        self.node.max_dissatisfaction_size(one_cost)
+    }
+
+    /// Maximum number of witness elements used to dissatisfy the Miniscript
+    /// fragment, including the witness script itself, or `None` if the
+    /// Miniscript cannot be dissatisfied.
+    pub fn max_dissatisfaction_witness_elements(&self) -> Option<usize> {
+        self.node
+            .max_dissatisfaction_witness_elements()
+            .map(|n| n + 1)
+    }
 }
 
 impl<Pk: MiniscriptKey> Miniscript<Pk> {
@@ -220,13 +241,77 @@ impl<Pk: MiniscriptKey + ToPublicKey> Miniscript<Pk> {
     /// Attempt to produce a satisfying witness for the
     /// witness script represented by the parse tree
     pub fn satisfy<S: satisfy::Satisfier<Pk>>(&self, satisfier: S) -> Option<Vec<Vec<u8>>> {
-        match satisfy::Satisfaction::satisfy(&self.node, &satisfier).stack {
+        self.satisfy_with_strategy(satisfier, &satisfy::MinWeight)
+    }
+
+    /// Attempt to produce a satisfying witness for the witness script
+    /// represented by the parse tree, using `strategy` to choose between
+    /// alternative satisfactions
+    pub fn satisfy_with_strategy<S, St>(&self, satisfier: S, strategy: &St) -> Option<Vec<Vec<u8>>>
+    where
+        S: satisfy::Satisfier<Pk>,
+        St: satisfy::SatisfactionStrategy,
+    {
+        self.satisfy_with_timelocks(satisfier, strategy)
+            .map(|(stack, _)| stack)
+    }
+
+    /// Attempt to produce a satisfying witness as `satisfy_with_strategy`
+    /// does, also returning the timelocks used by the chosen satisfaction,
+    /// which the spending transaction has to meet
+    pub fn satisfy_with_timelocks<S, St>(
+        &self,
+        satisfier: S,
+        strategy: &St,
+    ) -> Option<(Vec<Vec<u8>>, satisfy::Timelocks)>
+    where
+        S: satisfy::Satisfier<Pk>,
+        St: satisfy::SatisfactionStrategy,
+    {
+        let sat = satisfy::Satisfaction::satisfy(&self.node, &satisfier, strategy);
+        match sat.stack {
+            satisfy::Witness::Stack(stack) => Some((stack, sat.timelocks)),
+            satisfy::Witness::Unavailable => None,
+        }
+    }
+
+    /// Computes the minimal sets of signatures, hash preimages and timelocks
+    /// which are missing from the satisfier, any one of which would allow the
+    /// Miniscript to be satisfied. Useful to explain why `satisfy` failed.
+    ///
+    /// Fails if more than `max_sets` sets would be needed. See
+    /// `satisfy::Missing::minimal_sets` for the exact semantics.
+    pub fn missing_items<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        max_sets: usize,
+    ) -> Result<Vec<Vec<satisfy::Missing>>, Error> {
+        satisfy::Missing::minimal_sets(&self.node, &satisfier, max_sets)
+    }
+
+    /// Attempt to produce a dissatisfying witness for the witness script
+    /// represented by the parse tree. Some fragments (e.g. `pk_h`) need
+    /// data from the satisfier even to be dissatisfied.
+    ///
+    /// Returns `None` if no dissatisfaction exists, or if the only ones
+    /// available could be malleated by a third party.
+    pub fn dissatisfy<S: satisfy::Satisfier<Pk>>(&self, satisfier: S) -> Option<Vec<Vec<u8>>> {
+        match satisfy::Satisfaction::dissatisfy(&self.node, &satisfier, &satisfy::MinWeight).stack {
             satisfy::Witness::Stack(stack) => Some(stack),
             satisfy::Witness::Unavailable => None,
         }
     }
 }
 
+impl<Pk: MiniscriptKey> Miniscript<Pk> {
+    /// Whether the Miniscript has a unique dissatisfaction which is always
+    /// available. If this is `false`, any dissatisfaction that can be produced
+    /// may be malleated into a different one by a third party.
+    pub fn has_nonmalleable_dissatisfaction(&self) -> bool {
+        self.ty.mall.dissat == types::Dissat::Unique
+    }
+}
+
 impl<Pk> expression::FromTree for Arc<Miniscript<Pk>>
 where
     Pk: MiniscriptKey,
@@ -339,6 +424,7 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::satisfy::{self, Masked};
     use super::Miniscript;
     use hex_script;
     use miniscript::decode::Terminal;
@@ -349,10 +435,12 @@ mod tests {
 
     use bitcoin::hashes::{hash160, sha256, Hash};
     use bitcoin::{self, secp256k1};
+    use std::collections::HashMap;
     use std::str;
     use std::str::FromStr;
     use std::sync::Arc;
     use MiniscriptKey;
+    use {AbsLockTime, RelLockTime, Satisfier, SatisfierBuilder};
 
     type BScript = Miniscript<bitcoin::PublicKey>;
 
@@ -650,6 +738,228 @@ mod tests {
         );
     }
 
+    #[test]
+    fn satisfier_builder() {
+        let secp = secp256k1::Secp256k1::new();
+        let keys = pubkeys(3);
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+        let mut sigser = sig.0.serialize_der().to_vec();
+        sigser.push(0x01);
+
+        let preimage = [0xab; 32];
+        let ms: BScript = ms_str!(
+            "andor(c:pk_h({}),sha256({}),and_v(vc:pk({}),after(100)))",
+            keys[0].to_pubkeyhash(),
+            sha256::Hash::hash(&preimage),
+            keys[1]
+        );
+
+        // Hash lock branch: signature for the pkh key and the preimage
+        let sat = SatisfierBuilder::new()
+            .add_sig(keys[0], sig)
+            .add_preimage(preimage);
+        assert_eq!(
+            ms.satisfy(&sat),
+            Some(vec![preimage.to_vec(), sigser.clone(), keys[0].to_bytes()])
+        );
+
+        // Timelock branch: the pkh key must still be revealed to dissatisfy
+        let sat = SatisfierBuilder::new()
+            .add_sig(keys[1], sig)
+            .add_pk(keys[0])
+            .after(100);
+        assert_eq!(
+            ms.satisfy(&sat),
+            Some(vec![sigser.clone(), vec![], keys[0].to_bytes()])
+        );
+        assert_eq!(ms.satisfy(sat.after(99)), None);
+
+        // Plain preimage maps
+        let mut map = HashMap::new();
+        map.insert(sha256::Hash::hash(&preimage), preimage);
+        let hash_ms: BScript = ms_str!("sha256({})", sha256::Hash::hash(&preimage));
+        assert_eq!(hash_ms.satisfy(&map), Some(vec![preimage.to_vec()]));
+    }
+
+    #[test]
+    fn satisfier_composition() {
+        let secp = secp256k1::Secp256k1::new();
+        let keys = pubkeys(3);
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+
+        let ms: BScript = ms_str!("thresh_m(2,{},{},{})", keys[0], keys[1], keys[2]);
+
+        let mut alice = HashMap::new();
+        alice.insert(keys[0], sig);
+        let mut bob = HashMap::new();
+        bob.insert(keys[1], sig);
+        let mut carol = HashMap::new();
+        carol.insert(keys[2], sig);
+
+        let sats: Vec<Box<Satisfier<bitcoin::PublicKey>>> = vec![
+            Box::new(alice.clone()),
+            Box::new(bob.clone()),
+            Box::new(carol.clone()),
+        ];
+        assert!(ms.satisfy(&sats).is_some());
+
+        // Bob refuses to sign; Alice and Carol are still enough
+        let masked = Masked::new(&sats).mask_key(keys[1]);
+        assert!(ms.satisfy(&masked).is_some());
+        let masked = masked.mask_key(keys[2]);
+        assert_eq!(ms.satisfy(&masked), None);
+        let mut expected = vec![
+            vec![satisfy::Missing::Sig(keys[1])],
+            vec![satisfy::Missing::Sig(keys[2])],
+        ];
+        expected.sort();
+        assert_eq!(ms.missing_items(&masked, 100).unwrap(), expected);
+    }
+
+    #[test]
+    fn satisfaction_strategy() {
+        let secp = secp256k1::Secp256k1::new();
+        let keys = pubkeys(3);
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+        let mut sigser = sig.0.serialize_der().to_vec();
+        sigser.push(0x01);
+
+        let preimage = [0xab; 32];
+        let ms: BScript = ms_str!(
+            "or_i(and_v(v:sha256({}),c:pk({})),and_v(vc:pk({}),c:pk_h({})))",
+            sha256::Hash::hash(&preimage),
+            keys[0],
+            keys[2],
+            keys[1].to_pubkeyhash()
+        );
+        let sat = SatisfierBuilder::new()
+            .add_sig(keys[0], sig)
+            .add_sig(keys[1], sig)
+            .add_sig(keys[2], sig)
+            .add_preimage(preimage);
+
+        let hash_branch = vec![sigser.clone(), preimage.to_vec(), vec![1]];
+        let key_branch = vec![sigser.clone(), keys[1].to_bytes(), sigser.clone(), vec![]];
+
+        // The hash branch is lighter, but reveals the preimage
+        let weight = |stack: &[Vec<u8>]| stack.iter().map(|elem| elem.len() + 1).sum::<usize>();
+        assert!(weight(&hash_branch) < weight(&key_branch));
+        assert_eq!(ms.satisfy(&sat), Some(hash_branch.clone()));
+        assert_eq!(
+            ms.satisfy_with_strategy(&sat, &satisfy::MinWeight),
+            Some(hash_branch.clone())
+        );
+        assert_eq!(
+            ms.satisfy_with_strategy(&sat, &satisfy::MinRevealed),
+            Some(key_branch.clone())
+        );
+        let prefer_hash = |_: &[Vec<u8>], r: &satisfy::Revealed| (r.preimages == 0) as u64;
+        assert_eq!(
+            ms.satisfy_with_strategy(&sat, &prefer_hash),
+            Some(hash_branch)
+        );
+
+        // Extra signatures are dropped from multisig satisfactions
+        let ms: BScript = ms_str!("thresh_m(1,{},{})", keys[0], keys[1]);
+        assert_eq!(ms.satisfy(&sat), Some(vec![vec![], sigser.clone()]));
+
+        // Custom costs may exceed `i64::MAX` without upsetting `thresh`
+        let msg = secp256k1::Message::from_slice(&[3; 32]).unwrap();
+        let cheap_sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+        let mut cheap_sigser = cheap_sig.0.serialize_der().to_vec();
+        cheap_sigser.push(0x01);
+        let sat = SatisfierBuilder::new()
+            .add_sig(keys[0], sig)
+            .add_sig(keys[1], cheap_sig);
+        let expensive = |stack: &[Vec<u8>], _: &satisfy::Revealed| {
+            if stack.contains(&sigser) {
+                !0
+            } else {
+                stack.len() as u64
+            }
+        };
+        let ms: BScript = ms_str!("thresh(1,c:pk({}),sc:pk({}))", keys[0], keys[1]);
+        assert_eq!(
+            ms.satisfy_with_strategy(&sat, &expensive),
+            Some(vec![cheap_sigser, vec![]])
+        );
+    }
+
+    #[test]
+    fn timelocks() {
+        // Zero values and the BIP68 disable flag are rejected when parsing
+        assert!(BScript::from_str("older(0)").is_err());
+        assert!(BScript::from_str("older(4194304)").is_err()); // 0 time units
+        assert!(BScript::from_str("older(2147483649)").is_err());
+        assert!(BScript::from_str("after(0)").is_err());
+        assert!(BScript::from_str("after(4294967296)").is_err());
+        for &n in &[2147483647u32, 2147483648, 4294967295] {
+            let ms = BScript::from_str(&format!("after({})", n)).unwrap();
+            assert_eq!(BScript::parse(&ms.encode()).unwrap(), ms);
+            assert_eq!(ms.script_size(), ms.encode().len());
+        }
+
+        let blocks = RelLockTime::from_height(144).unwrap();
+        let time = RelLockTime::from_512_second_intervals(144).unwrap();
+        assert!(blocks.is_height_locked());
+        assert!(time.is_time_locked());
+        assert_eq!(time.value(), 144);
+        assert!(blocks.is_satisfied_by(144));
+        assert!(!blocks.is_satisfied_by(143));
+        assert!(!blocks.is_satisfied_by(time.to_consensus_u32()));
+        assert!(time.is_satisfied_by(time.to_consensus_u32()));
+        assert!(!blocks.is_satisfied_by(144 | (1 << 31)));
+
+        let height = AbsLockTime::from_consensus(600_000).unwrap();
+        let timestamp = AbsLockTime::from_consensus(1_500_000_000).unwrap();
+        assert!(height.is_block_height());
+        assert!(timestamp.is_block_time());
+        assert!(height.is_satisfied_by(600_000));
+        assert!(!height.is_satisfied_by(599_999));
+        // A timestamp is numerically larger but not comparable to a height
+        assert!(!height.is_satisfied_by(1_500_000_000));
+        assert!(timestamp.is_satisfied_by(u32::max_value()));
+
+        // Satisfiers compare `nSequence` and `nLockTime` by consensus rules
+        let ms: BScript = ms_str!("and_v(v:older(144),after(600000))");
+        assert!(ms
+            .satisfy((satisfy::Older(144), satisfy::After(600_000)))
+            .is_some());
+        let sat = (
+            satisfy::Older(time.to_consensus_u32()),
+            satisfy::After(600_000),
+        );
+        assert_eq!(ms.satisfy(sat), None);
+        let sat = SatisfierBuilder::new().older(144).after(1_500_000_000);
+        assert_eq!(ms.satisfy(&sat), None);
+
+        // Heights and times cannot be mixed within one satisfaction
+        let height = satisfy::Timelocks {
+            after: Some(height),
+            ..satisfy::Timelocks::default()
+        };
+        let timestamp = satisfy::Timelocks {
+            after: Some(timestamp),
+            ..satisfy::Timelocks::default()
+        };
+        assert_eq!(height.combine(height), Some(height));
+        assert_eq!(height.combine(timestamp), None);
+        let sats = vec![satisfy::After(700_000), satisfy::After(1_500_000_000)];
+        let ms: BScript = ms_str!("and_v(v:after(600000),after(1500000000))");
+        assert_eq!(ms.satisfy(&sats), None);
+        let ms: BScript = ms_str!("and_v(v:after(600000),after(700000))");
+        let (_, locks) = ms
+            .satisfy_with_timelocks(&sats, &satisfy::MinWeight)
+            .unwrap();
+        assert_eq!(locks.after, AbsLockTime::from_consensus(700_000).ok());
+    }
+
     #[test]
     fn deserialize() {
         // Most of these came from fuzzing, hence the increasing lengths
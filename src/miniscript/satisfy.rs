@@ -18,15 +18,19 @@
 //! scriptpubkeys.
 //!
 
-use std::collections::HashMap;
-use std::{cmp, i64, mem};
+use std::collections::{HashMap, HashSet};
+use std::{cmp, fmt, i64, mem, ops, u64};
 
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use bitcoin::{self, secp256k1};
-use {MiniscriptKey, ToPublicKey};
+use miniscript::timelock::{AbsLockTime, RelLockTime, LOCKTIME_THRESHOLD};
+use {Error, MiniscriptKey, ToPublicKey};
 
 use Terminal;
 
+/// `nSequence` value which disables both relative and absolute locktimes
+const SEQUENCE_FINAL: u32 = 0xffffffff;
+
 /// Type alias for a signature/hashtype pair
 pub type BitcoinSig = (secp256k1::Signature, bitcoin::SigHashType);
 
@@ -73,13 +77,14 @@ pub trait Satisfier<Pk: MiniscriptKey> {
         None
     }
 
-    /// Assert whether an relative locktime is satisfied
-    fn check_older(&self, _: u32) -> bool {
+    /// Assert whether a relative locktime is satisfied by the spending input
+    fn check_older(&self, _: RelLockTime) -> bool {
         false
     }
 
-    /// Assert whether a absolute locktime is satisfied
-    fn check_after(&self, _: u32) -> bool {
+    /// Assert whether an absolute locktime is satisfied by the spending
+    /// transaction
+    fn check_after(&self, _: AbsLockTime) -> bool {
         false
     }
 }
@@ -87,23 +92,23 @@ pub trait Satisfier<Pk: MiniscriptKey> {
 // Allow use of `()` as a "no conditions available" satisfier
 impl<Pk: MiniscriptKey> Satisfier<Pk> for () {}
 
-/// Newtype around `u32` which implements `Satisfier` using `n` as an
-/// relative locktime
+/// Newtype around `u32` which implements `Satisfier` using `n` as the
+/// `nSequence` of the spending input, which determines its relative locktime
 pub struct Older(pub u32);
 
 impl<Pk: MiniscriptKey> Satisfier<Pk> for Older {
-    fn check_older(&self, n: u32) -> bool {
-        n <= self.0
+    fn check_older(&self, n: RelLockTime) -> bool {
+        n.is_satisfied_by(self.0)
     }
 }
 
-/// Newtype around `u32` which implements `Satisfier` using `n` as an
-/// absolute locktime
+/// Newtype around `u32` which implements `Satisfier` using `n` as the
+/// `nLockTime` of the spending transaction
 pub struct After(pub u32);
 
 impl<Pk: MiniscriptKey> Satisfier<Pk> for After {
-    fn check_after(&self, n: u32) -> bool {
-        n <= self.0
+    fn check_after(&self, n: AbsLockTime) -> bool {
+        n.is_satisfied_by(self.0)
     }
 }
 
@@ -131,6 +136,153 @@ where
     }
 }
 
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<sha256::Hash, [u8; 32]> {
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<sha256d::Hash, [u8; 32]> {
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<ripemd160::Hash, [u8; 32]> {
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<hash160::Hash, [u8; 32]> {
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+/// General-purpose satisfier which collects signatures, public keys, hash
+/// preimages and timelock information in one place. Since the builder
+/// itself implements `Satisfier`, there is no separate build step.
+#[derive(Clone, Debug)]
+pub struct SatisfierBuilder<Pk: MiniscriptKey> {
+    sigs: HashMap<Pk, BitcoinSig>,
+    pkh_pks: HashMap<Pk::Hash, Pk>,
+    sha256_preimages: HashMap<sha256::Hash, [u8; 32]>,
+    hash256_preimages: HashMap<sha256d::Hash, [u8; 32]>,
+    ripemd160_preimages: HashMap<ripemd160::Hash, [u8; 32]>,
+    hash160_preimages: HashMap<hash160::Hash, [u8; 32]>,
+    older: Option<u32>,
+    after: Option<u32>,
+}
+
+impl<Pk: MiniscriptKey> Default for SatisfierBuilder<Pk> {
+    fn default() -> Self {
+        SatisfierBuilder {
+            sigs: HashMap::new(),
+            pkh_pks: HashMap::new(),
+            sha256_preimages: HashMap::new(),
+            hash256_preimages: HashMap::new(),
+            ripemd160_preimages: HashMap::new(),
+            hash160_preimages: HashMap::new(),
+            older: None,
+            after: None,
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> SatisfierBuilder<Pk> {
+    /// Create a new satisfier with no data available
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a signature with the given key. The key is also made available
+    /// for `pk_h` fragments which commit to its hash.
+    pub fn add_sig(mut self, pk: Pk, sig: BitcoinSig) -> Self {
+        self.pkh_pks.insert(pk.to_pubkeyhash(), pk.clone());
+        self.sigs.insert(pk, sig);
+        self
+    }
+
+    /// Add a public key without a signature. This allows dissatisfying
+    /// `pk_h` fragments which commit to its hash.
+    pub fn add_pk(mut self, pk: Pk) -> Self {
+        self.pkh_pks.insert(pk.to_pubkeyhash(), pk);
+        self
+    }
+
+    /// Add a hash preimage. It will be used for any of the supported hash
+    /// functions whose output matches a hash in the script.
+    pub fn add_preimage(mut self, preimage: [u8; 32]) -> Self {
+        self.sha256_preimages
+            .insert(sha256::Hash::hash(&preimage), preimage);
+        self.hash256_preimages
+            .insert(sha256d::Hash::hash(&preimage), preimage);
+        self.ripemd160_preimages
+            .insert(ripemd160::Hash::hash(&preimage), preimage);
+        self.hash160_preimages
+            .insert(hash160::Hash::hash(&preimage), preimage);
+        self
+    }
+
+    /// Set the `nSequence` of the spending input, which is used to check
+    /// `older` fragments
+    pub fn older(mut self, n: u32) -> Self {
+        self.older = Some(n);
+        self
+    }
+
+    /// Set the `nLockTime` of the spending transaction, which is used to
+    /// check `after` fragments
+    pub fn after(mut self, n: u32) -> Self {
+        self.after = Some(n);
+        self
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for SatisfierBuilder<Pk> {
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        self.sigs.get(pk).cloned()
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.pkh_pks.get(pkh).cloned()
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        let pk = self.pkh_pks.get(pkh)?;
+        self.sigs.get(pk).map(|sig| (pk.to_public_key(), *sig))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.sha256_preimages.get(&h).cloned()
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.hash256_preimages.get(&h).cloned()
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.ripemd160_preimages.get(&h).cloned()
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.hash160_preimages.get(&h).cloned()
+    }
+
+    fn check_older(&self, n: RelLockTime) -> bool {
+        self.older
+            .map(|seq| n.is_satisfied_by(seq))
+            .unwrap_or(false)
+    }
+
+    fn check_after(&self, n: AbsLockTime) -> bool {
+        self.after
+            .map(|lock_time| n.is_satisfied_by(lock_time))
+            .unwrap_or(false)
+    }
+}
+
 impl<'a, Pk: MiniscriptKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a S {
     fn lookup_sig(&self, p: &Pk) -> Option<BitcoinSig> {
         (**self).lookup_sig(p)
@@ -160,11 +312,11 @@ impl<'a, Pk: MiniscriptKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a S {
         (**self).lookup_hash160(h)
     }
 
-    fn check_older(&self, t: u32) -> bool {
+    fn check_older(&self, t: RelLockTime) -> bool {
         (**self).check_older(t)
     }
 
-    fn check_after(&self, t: u32) -> bool {
+    fn check_after(&self, t: AbsLockTime) -> bool {
         (**self).check_after(t)
     }
 }
@@ -198,15 +350,228 @@ impl<'a, Pk: MiniscriptKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a mut S {
         (**self).lookup_hash160(h)
     }
 
-    fn check_older(&self, t: u32) -> bool {
+    fn check_older(&self, t: RelLockTime) -> bool {
         (**self).check_older(t)
     }
 
-    fn check_after(&self, t: u32) -> bool {
+    fn check_after(&self, t: AbsLockTime) -> bool {
         (**self).check_after(t)
     }
 }
 
+impl<Pk: MiniscriptKey, S: Satisfier<Pk> + ?Sized> Satisfier<Pk> for Box<S> {
+    fn lookup_sig(&self, p: &Pk) -> Option<BitcoinSig> {
+        (**self).lookup_sig(p)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        (**self).lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        (**self).lookup_pkh_sig(pkh)
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        (**self).lookup_sha256(h)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        (**self).lookup_hash256(h)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        (**self).lookup_ripemd160(h)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        (**self).lookup_hash160(h)
+    }
+
+    fn check_older(&self, t: RelLockTime) -> bool {
+        (**self).check_older(t)
+    }
+
+    fn check_after(&self, t: AbsLockTime) -> bool {
+        (**self).check_after(t)
+    }
+}
+
+// Allow use of a list of satisfiers, e.g. `Vec<Box<dyn Satisfier<Pk>>>`,
+// whose types or number are not known at compile time. They are queried
+// in order, and the first result found is used.
+impl<Pk: MiniscriptKey, S: Satisfier<Pk>> Satisfier<Pk> for Vec<S> {
+    fn lookup_sig(&self, p: &Pk) -> Option<BitcoinSig> {
+        self.iter().filter_map(|s| s.lookup_sig(p)).next()
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.iter().filter_map(|s| s.lookup_pkh_pk(pkh)).next()
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        self.iter().filter_map(|s| s.lookup_pkh_sig(pkh)).next()
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.iter().filter_map(|s| s.lookup_sha256(h)).next()
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.iter().filter_map(|s| s.lookup_hash256(h)).next()
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.iter().filter_map(|s| s.lookup_ripemd160(h)).next()
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.iter().filter_map(|s| s.lookup_hash160(h)).next()
+    }
+
+    fn check_older(&self, t: RelLockTime) -> bool {
+        self.iter().any(|s| s.check_older(t))
+    }
+
+    fn check_after(&self, t: AbsLockTime) -> bool {
+        self.iter().any(|s| s.check_after(t))
+    }
+}
+
+/// Satisfier which hides some of the signatures and hash preimages known
+/// to another satisfier. Useful to simulate scenarios where some party
+/// refuses to cooperate, e.g. a cosigner who will not sign.
+pub struct Masked<Pk: MiniscriptKey, S: Satisfier<Pk>> {
+    inner: S,
+    keys: HashSet<Pk>,
+    key_hashes: HashSet<Pk::Hash>,
+    sha256: HashSet<sha256::Hash>,
+    hash256: HashSet<sha256d::Hash>,
+    ripemd160: HashSet<ripemd160::Hash>,
+    hash160: HashSet<hash160::Hash>,
+}
+
+impl<Pk: MiniscriptKey, S: Satisfier<Pk>> Masked<Pk, S> {
+    /// Wrap a satisfier, initially without masking anything
+    pub fn new(inner: S) -> Self {
+        Masked {
+            inner,
+            keys: HashSet::new(),
+            key_hashes: HashSet::new(),
+            sha256: HashSet::new(),
+            hash256: HashSet::new(),
+            ripemd160: HashSet::new(),
+            hash160: HashSet::new(),
+        }
+    }
+
+    /// Hide all signatures with the given key, whether they are looked up
+    /// by key or by key hash. The key itself remains available to
+    /// dissatisfy `pk_h` fragments.
+    pub fn mask_key(mut self, pk: Pk) -> Self {
+        self.key_hashes.insert(pk.to_pubkeyhash());
+        self.keys.insert(pk);
+        self
+    }
+
+    /// Hide signatures for the key with the given hash
+    pub fn mask_key_hash(mut self, pkh: Pk::Hash) -> Self {
+        self.key_hashes.insert(pkh);
+        self
+    }
+
+    /// Hide the preimage of a SHA256 hash
+    pub fn mask_sha256(mut self, h: sha256::Hash) -> Self {
+        self.sha256.insert(h);
+        self
+    }
+
+    /// Hide the preimage of a HASH256 hash
+    pub fn mask_hash256(mut self, h: sha256d::Hash) -> Self {
+        self.hash256.insert(h);
+        self
+    }
+
+    /// Hide the preimage of a RIPEMD160 hash
+    pub fn mask_ripemd160(mut self, h: ripemd160::Hash) -> Self {
+        self.ripemd160.insert(h);
+        self
+    }
+
+    /// Hide the preimage of a HASH160 hash
+    pub fn mask_hash160(mut self, h: hash160::Hash) -> Self {
+        self.hash160.insert(h);
+        self
+    }
+
+    /// Unwrap the underlying satisfier
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<Pk: MiniscriptKey, S: Satisfier<Pk>> Satisfier<Pk> for Masked<Pk, S> {
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        if self.keys.contains(pk) || self.key_hashes.contains(&pk.to_pubkeyhash()) {
+            None
+        } else {
+            self.inner.lookup_sig(pk)
+        }
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.inner.lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        if self.key_hashes.contains(pkh) {
+            None
+        } else {
+            self.inner.lookup_pkh_sig(pkh)
+        }
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        if self.sha256.contains(&h) {
+            None
+        } else {
+            self.inner.lookup_sha256(h)
+        }
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        if self.hash256.contains(&h) {
+            None
+        } else {
+            self.inner.lookup_hash256(h)
+        }
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        if self.ripemd160.contains(&h) {
+            None
+        } else {
+            self.inner.lookup_ripemd160(h)
+        }
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        if self.hash160.contains(&h) {
+            None
+        } else {
+            self.inner.lookup_hash160(h)
+        }
+    }
+
+    fn check_older(&self, t: RelLockTime) -> bool {
+        self.inner.check_older(t)
+    }
+
+    fn check_after(&self, t: AbsLockTime) -> bool {
+        self.inner.check_after(t)
+    }
+}
+
 macro_rules! impl_tuple_satisfier {
     ($($ty:ident),*) => {
         #[allow(non_snake_case)]
@@ -291,7 +656,7 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
-            fn check_older(&self, n: u32) -> bool {
+            fn check_older(&self, n: RelLockTime) -> bool {
                 let &($(ref $ty,)*) = self;
                 $(
                     if $ty.check_older(n) {
@@ -301,7 +666,7 @@ macro_rules! impl_tuple_satisfier {
                 false
             }
 
-            fn check_after(&self, n: u32) -> bool {
+            fn check_after(&self, n: AbsLockTime) -> bool {
                 let &($(ref $ty,)*) = self;
                 $(
                     if $ty.check_after(n) {
@@ -469,6 +834,173 @@ impl Witness {
     }
 }
 
+/// Summary of the data revealed on-chain by a (dis)satisfaction, beyond
+/// the script itself
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Hash)]
+pub struct Revealed {
+    /// Number of signatures
+    pub sigs: usize,
+    /// Number of public keys revealed for `pk_h` fragments
+    pub pkh_keys: usize,
+    /// Number of hash preimages
+    pub preimages: usize,
+}
+
+impl ops::Add for Revealed {
+    type Output = Revealed;
+
+    fn add(self, other: Revealed) -> Revealed {
+        Revealed {
+            sigs: self.sigs + other.sigs,
+            pkh_keys: self.pkh_keys + other.pkh_keys,
+            preimages: self.preimages + other.preimages,
+        }
+    }
+}
+
+/// Strategy used to choose between several available satisfactions of a
+/// fragment. Whenever there is a choice, the satisfaction with the lowest
+/// cost is chosen.
+///
+/// Note that the choice is constrained by malleability: a satisfaction
+/// without a signature is always preferred over one with a signature,
+/// whatever their costs, since otherwise a third party could replace the
+/// chosen one.
+///
+/// Any `Fn(&[Vec<u8>], &Revealed) -> u64` can be used as a custom strategy.
+pub trait SatisfactionStrategy {
+    /// Cost of a witness stack, given the data it reveals
+    fn cost(&self, stack: &[Vec<u8>], revealed: &Revealed) -> u64;
+}
+
+impl<F: Fn(&[Vec<u8>], &Revealed) -> u64> SatisfactionStrategy for F {
+    fn cost(&self, stack: &[Vec<u8>], revealed: &Revealed) -> u64 {
+        self(stack, revealed)
+    }
+}
+
+/// Serialized size, in bytes, of a witness stack's elements, including
+/// their length prefixes
+fn stack_weight(stack: &[Vec<u8>]) -> u64 {
+    stack
+        .iter()
+        .map(|elem| bitcoin::VarInt(elem.len() as u64).len() + elem.len())
+        .sum::<usize>() as u64
+}
+
+/// Choose the satisfaction with the smallest witness
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct MinWeight;
+
+impl SatisfactionStrategy for MinWeight {
+    fn cost(&self, stack: &[Vec<u8>], _: &Revealed) -> u64 {
+        stack_weight(stack)
+    }
+}
+
+/// Choose the satisfaction which reveals the fewest hash preimages, then
+/// the fewest public keys and signatures, using the witness size only to
+/// break ties
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct MinRevealed;
+
+impl SatisfactionStrategy for MinRevealed {
+    fn cost(&self, stack: &[Vec<u8>], revealed: &Revealed) -> u64 {
+        // Witnesses are limited to a few megabytes, so this cannot overflow
+        // or be dominated by the weight
+        (revealed.preimages as u64) << 48
+            | ((revealed.pkh_keys + revealed.sigs) as u64) << 32
+            | stack_weight(stack)
+    }
+}
+
+/// Timelocks which a spending transaction must meet for a satisfaction to
+/// be valid. `older` is enforced through the input's `nSequence` (and needs
+/// a transaction version of at least 2), `after` through the transaction's
+/// `nLockTime` (and needs a non-final `nSequence`).
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Timelocks {
+    /// The largest relative locktime used, if any
+    pub older: Option<RelLockTime>,
+    /// The largest absolute locktime used, if any
+    pub after: Option<AbsLockTime>,
+}
+
+impl Timelocks {
+    /// Combine the timelocks of two parts of a satisfaction, or return
+    /// `None` if they mix heights and times, which no single transaction
+    /// can satisfy
+    pub fn combine(self, other: Timelocks) -> Option<Timelocks> {
+        let older = match (self.older, other.older) {
+            (Some(a), Some(b)) if !a.is_same_unit(b) => return None,
+            (Some(a), Some(b)) => Some(if a.is_implied_by(b) { b } else { a }),
+            (a, b) => a.or(b),
+        };
+        let after = match (self.after, other.after) {
+            (Some(a), Some(b)) if !a.is_same_unit(b) => return None,
+            (a, b) => cmp::max(a, b),
+        };
+        Some(Timelocks { older, after })
+    }
+
+    /// The `nSequence` which the spending input should have, given its
+    /// current value `sequence`
+    pub fn sequence(&self, sequence: u32) -> u32 {
+        match (self.older, self.after) {
+            (Some(older), _) if !older.is_satisfied_by(sequence) => older.to_consensus_u32(),
+            (None, Some(_)) if sequence == SEQUENCE_FINAL => SEQUENCE_FINAL - 1,
+            _ => sequence,
+        }
+    }
+
+    /// Set the `nSequence` of input `index`, and the `nLockTime` and version
+    /// of `tx`, so that the timelocks are met. Fails without modifying the
+    /// transaction if the required locktime mixes heights and times with
+    /// the one already set, or if the version, locktime or sequence would
+    /// have to change while other inputs already have a scriptSig or
+    /// witness, since their signatures would be invalidated.
+    ///
+    /// Any signatures for input `index` itself must commit to the resulting
+    /// values. Panics if `index` is out of range.
+    pub fn apply(&self, tx: &mut bitcoin::Transaction, index: usize) -> Result<(), Error> {
+        let sequence = self.sequence(tx.input[index].sequence);
+        let version = match self.older {
+            Some(_) if tx.version < 2 => 2,
+            _ => tx.version,
+        };
+        let lock_time = match self.after {
+            Some(after) if !after.is_satisfied_by(tx.lock_time) => {
+                if tx.lock_time != 0
+                    && (tx.lock_time < LOCKTIME_THRESHOLD) != after.is_block_height()
+                {
+                    return Err(Error::LockTimeConflict(
+                        after.to_consensus_u32(),
+                        tx.lock_time,
+                    ));
+                }
+                after.to_consensus_u32()
+            }
+            _ => tx.lock_time,
+        };
+
+        if version != tx.version
+            || lock_time != tx.lock_time
+            || sequence != tx.input[index].sequence
+        {
+            for (i, txin) in tx.input.iter().enumerate() {
+                if i != index && (!txin.script_sig.is_empty() || !txin.witness.is_empty()) {
+                    return Err(Error::InputAlreadySatisfied(i));
+                }
+            }
+        }
+
+        tx.version = version;
+        tx.lock_time = lock_time;
+        tx.input[index].sequence = sequence;
+        Ok(())
+    }
+}
+
 /// A (dis)satisfaction of a Miniscript fragment
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Satisfaction {
@@ -477,16 +1009,31 @@ pub struct Satisfaction {
     /// Whether or not this (dis)satisfaction has a signature somewhere
     /// in it
     pub has_sig: bool,
+    /// Timelocks which the spending transaction must meet
+    pub timelocks: Timelocks,
+    /// Data revealed by the witness
+    pub revealed: Revealed,
 }
 
 impl Satisfaction {
-    fn minimum(sat1: Self, sat2: Self) -> Self {
+    /// The cost of this (dis)satisfaction according to the given strategy;
+    /// unavailable witnesses have maximal cost
+    fn cost<St: SatisfactionStrategy>(&self, strategy: &St) -> u64 {
+        match self.stack {
+            Witness::Stack(ref s) => strategy.cost(s, &self.revealed),
+            Witness::Unavailable => u64::MAX,
+        }
+    }
+
+    fn minimum<St: SatisfactionStrategy>(sat1: Self, sat2: Self, strategy: &St) -> Self {
         match (sat1.has_sig, sat2.has_sig) {
             // If neither option has a signature, this is a malleability
             // vector, so choose neither one.
             (false, false) => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             // If only one has a signature, take the one that doesn't; a
             // third party could malleate by removing the signature, but
@@ -494,34 +1041,75 @@ impl Satisfaction {
             (false, true) => Satisfaction {
                 stack: sat1.stack,
                 has_sig: false,
+                timelocks: sat1.timelocks,
+                revealed: sat1.revealed,
             },
             (true, false) => Satisfaction {
                 stack: sat2.stack,
                 has_sig: false,
+                timelocks: sat2.timelocks,
+                revealed: sat2.revealed,
             },
             // If both have a signature associated with them, choose the
-            // cheaper one (where "cheaper" is defined such that available
-            // things are cheaper than unavailable ones)
-            (true, true) => Satisfaction {
-                stack: cmp::min(sat1.stack, sat2.stack),
-                has_sig: true,
-            },
+            // cheaper one (where "cheaper" is defined by the strategy, and
+            // such that available things are cheaper than unavailable ones)
+            (true, true) => {
+                if sat2.cost(strategy) < sat1.cost(strategy) {
+                    sat2
+                } else {
+                    sat1
+                }
+            }
+        }
+    }
+
+    /// Combine two (dis)satisfactions, placing `two` on top of `one`. If
+    /// their timelocks cannot be met by a single transaction, the result is
+    /// unavailable.
+    fn combine(one: Self, two: Self) -> Self {
+        let (stack, timelocks) = match one.timelocks.combine(two.timelocks) {
+            Some(timelocks) => (Witness::combine(one.stack, two.stack), timelocks),
+            None => (Witness::Unavailable, Timelocks::default()),
+        };
+        Satisfaction {
+            stack,
+            has_sig: one.has_sig || two.has_sig,
+            timelocks,
+            revealed: one.revealed + two.revealed,
         }
     }
 
     /// Produce a satisfaction
-    pub fn satisfy<Pk: MiniscriptKey + ToPublicKey, Sat: Satisfier<Pk>>(
-        term: &Terminal<Pk>,
-        stfr: &Sat,
-    ) -> Self {
+    pub fn satisfy<Pk, Sat, St>(term: &Terminal<Pk>, stfr: &Sat, strategy: &St) -> Self
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Sat: Satisfier<Pk>,
+        St: SatisfactionStrategy,
+    {
+        let preimage = Revealed {
+            preimages: 1,
+            ..Revealed::default()
+        };
+
         match *term {
             Terminal::Pk(ref pk) => Satisfaction {
                 stack: Witness::signature(stfr, pk),
                 has_sig: true,
+                timelocks: Timelocks::default(),
+                revealed: Revealed {
+                    sigs: 1,
+                    ..Revealed::default()
+                },
             },
             Terminal::PkH(ref pkh) => Satisfaction {
                 stack: Witness::pkh_signature(stfr, pkh),
                 has_sig: true,
+                timelocks: Timelocks::default(),
+                revealed: Revealed {
+                    sigs: 1,
+                    pkh_keys: 1,
+                    ..Revealed::default()
+                },
             },
             Terminal::After(t) => Satisfaction {
                 stack: if stfr.check_after(t) {
@@ -530,6 +1118,11 @@ impl Satisfaction {
                     Witness::Unavailable
                 },
                 has_sig: false,
+                timelocks: Timelocks {
+                    after: Some(t),
+                    ..Timelocks::default()
+                },
+                revealed: Revealed::default(),
             },
             Terminal::Older(t) => Satisfaction {
                 stack: if stfr.check_older(t) {
@@ -538,140 +1131,151 @@ impl Satisfaction {
                     Witness::Unavailable
                 },
                 has_sig: false,
+                timelocks: Timelocks {
+                    older: Some(t),
+                    ..Timelocks::default()
+                },
+                revealed: Revealed::default(),
             },
             Terminal::Ripemd160(h) => Satisfaction {
                 stack: Witness::ripemd160_preimage(stfr, h),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: preimage,
             },
             Terminal::Hash160(h) => Satisfaction {
                 stack: Witness::hash160_preimage(stfr, h),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: preimage,
             },
             Terminal::Sha256(h) => Satisfaction {
                 stack: Witness::sha256_preimage(stfr, h),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: preimage,
             },
             Terminal::Hash256(h) => Satisfaction {
                 stack: Witness::hash256_preimage(stfr, h),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: preimage,
             },
             Terminal::True => Satisfaction {
                 stack: Witness::empty(),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::False => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::Alt(ref sub)
             | Terminal::Swap(ref sub)
             | Terminal::Check(ref sub)
             | Terminal::Verify(ref sub)
             | Terminal::NonZero(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => Self::satisfy(&sub.node, stfr),
+            | Terminal::ZeroNotEqual(ref sub) => Self::satisfy(&sub.node, stfr, strategy),
             Terminal::DupIf(ref sub) => {
-                let sat = Self::satisfy(&sub.node, stfr);
+                let sat = Self::satisfy(&sub.node, stfr, strategy);
                 Satisfaction {
                     stack: Witness::combine(sat.stack, Witness::push_1()),
                     has_sig: sat.has_sig,
+                    timelocks: sat.timelocks,
+                    revealed: sat.revealed,
                 }
             }
             Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
-                Satisfaction {
-                    stack: Witness::combine(l_sat.stack, r_sat.stack),
-                    has_sig: l_sat.has_sig || r_sat.has_sig,
-                }
+                let l_sat = Self::satisfy(&l.node, stfr, strategy);
+                let r_sat = Self::satisfy(&r.node, stfr, strategy);
+                Self::combine(r_sat, l_sat)
             }
             Terminal::AndOr(ref a, ref b, ref c) => {
-                let a_sat = Self::satisfy(&a.node, stfr);
-                let a_nsat = Self::dissatisfy(&a.node, stfr);
-                let b_sat = Self::satisfy(&b.node, stfr);
-                let c_sat = Self::satisfy(&c.node, stfr);
+                let a_sat = Self::satisfy(&a.node, stfr, strategy);
+                let a_nsat = Self::dissatisfy(&a.node, stfr, strategy);
+                let b_sat = Self::satisfy(&b.node, stfr, strategy);
+                let c_sat = Self::satisfy(&c.node, stfr, strategy);
 
                 Self::minimum(
-                    Satisfaction {
-                        stack: Witness::combine(a_sat.stack, b_sat.stack),
-                        has_sig: a_sat.has_sig || b_sat.has_sig,
-                    },
-                    Satisfaction {
-                        stack: Witness::combine(a_nsat.stack, c_sat.stack),
-                        has_sig: a_nsat.has_sig || c_sat.has_sig,
-                    },
+                    Self::combine(b_sat, a_sat),
+                    Self::combine(c_sat, a_nsat),
+                    strategy,
                 )
             }
             Terminal::OrB(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
-                let l_nsat = Self::dissatisfy(&l.node, stfr);
-                let r_nsat = Self::dissatisfy(&r.node, stfr);
+                let l_sat = Self::satisfy(&l.node, stfr, strategy);
+                let r_sat = Self::satisfy(&r.node, stfr, strategy);
+                let l_nsat = Self::dissatisfy(&l.node, stfr, strategy);
+                let r_nsat = Self::dissatisfy(&r.node, stfr, strategy);
 
                 assert!(!l_nsat.has_sig);
                 assert!(!r_nsat.has_sig);
 
                 Self::minimum(
-                    Satisfaction {
-                        stack: Witness::combine(r_sat.stack, l_nsat.stack),
-                        has_sig: r_sat.has_sig,
-                    },
-                    Satisfaction {
-                        stack: Witness::combine(r_nsat.stack, l_sat.stack),
-                        has_sig: l_sat.has_sig,
-                    },
+                    Self::combine(r_sat, l_nsat),
+                    Self::combine(r_nsat, l_sat),
+                    strategy,
                 )
             }
             Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
-                let l_nsat = Self::dissatisfy(&l.node, stfr);
+                let l_sat = Self::satisfy(&l.node, stfr, strategy);
+                let r_sat = Self::satisfy(&r.node, stfr, strategy);
+                let l_nsat = Self::dissatisfy(&l.node, stfr, strategy);
 
                 assert!(!l_nsat.has_sig);
 
-                Self::minimum(
-                    l_sat,
-                    Satisfaction {
-                        stack: Witness::combine(r_sat.stack, l_nsat.stack),
-                        has_sig: r_sat.has_sig,
-                    },
-                )
+                Self::minimum(l_sat, Self::combine(r_sat, l_nsat), strategy)
             }
             Terminal::OrI(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
+                let l_sat = Self::satisfy(&l.node, stfr, strategy);
+                let r_sat = Self::satisfy(&r.node, stfr, strategy);
                 Self::minimum(
                     Satisfaction {
                         stack: Witness::combine(l_sat.stack, Witness::push_1()),
                         has_sig: l_sat.has_sig,
+                        timelocks: l_sat.timelocks,
+                        revealed: l_sat.revealed,
                     },
                     Satisfaction {
                         stack: Witness::combine(r_sat.stack, Witness::push_0()),
                         has_sig: r_sat.has_sig,
+                        timelocks: r_sat.timelocks,
+                        revealed: r_sat.revealed,
                     },
+                    strategy,
                 )
             }
             Terminal::Thresh(k, ref subs) => {
                 let mut sats = subs
                     .iter()
-                    .map(|s| Self::satisfy(&s.node, stfr))
+                    .map(|s| Self::satisfy(&s.node, stfr, strategy))
                     .collect::<Vec<_>>();
                 // Start with the to-return stack set to all dissatisfactions
                 let mut ret_stack = subs
                     .iter()
-                    .map(|s| Self::dissatisfy(&s.node, stfr))
+                    .map(|s| Self::dissatisfy(&s.node, stfr, strategy))
                     .collect::<Vec<_>>();
 
                 // Sort everything by (sat cost - dissat cost), except that
                 // satisfactions without signatures beat satisfactions with
-                // signatures
+                // signatures. Costs from custom strategies may be up to
+                // `u64::MAX`, so the difference saturates short of the
+                // weights of unavailable witnesses.
+                let saturating_diff =
+                    |a: u64, b: u64| cmp::min(a.saturating_sub(b), i64::MAX as u64 - 1) as i64;
                 let mut sat_indices = (0..subs.len()).collect::<Vec<_>>();
                 sat_indices.sort_by_key(|&i| {
                     let stack_weight = match (&sats[i].stack, &ret_stack[i].stack) {
                         (&Witness::Unavailable, _) => i64::MAX,
                         (_, &Witness::Unavailable) => i64::MIN,
-                        (&Witness::Stack(ref s), &Witness::Stack(ref d)) => {
-                            s.iter().map(Vec::len).sum::<usize>() as i64
-                                - d.iter().map(Vec::len).sum::<usize>() as i64
+                        (&Witness::Stack(..), &Witness::Stack(..)) => {
+                            let sat_cost = sats[i].cost(strategy);
+                            let dissat_cost = ret_stack[i].cost(strategy);
+                            saturating_diff(sat_cost, dissat_cost)
+                                - saturating_diff(dissat_cost, sat_cost)
                         }
                     };
                     (sats[i].has_sig, stack_weight)
@@ -696,15 +1300,20 @@ impl Satisfaction {
                     Satisfaction {
                         stack: Witness::Unavailable,
                         has_sig: false,
+                        timelocks: Timelocks::default(),
+                        revealed: Revealed::default(),
                     }
                 } else {
                     // Otherwise flatten everything out
-                    Satisfaction {
-                        has_sig: ret_stack.iter().any(|sat| sat.has_sig),
-                        stack: ret_stack.into_iter().fold(Witness::empty(), |acc, next| {
-                            Witness::combine(next.stack, acc)
-                        }),
-                    }
+                    ret_stack.into_iter().fold(
+                        Satisfaction {
+                            stack: Witness::empty(),
+                            has_sig: false,
+                            timelocks: Timelocks::default(),
+                            revealed: Revealed::default(),
+                        },
+                        |acc, next| Self::combine(next, acc),
+                    )
                 }
             }
             Terminal::ThreshM(k, ref keys) => {
@@ -721,21 +1330,31 @@ impl Satisfaction {
                     }
                 }
 
+                let revealed = Revealed {
+                    sigs: k,
+                    ..Revealed::default()
+                };
                 if sig_count < k {
                     Satisfaction {
                         stack: Witness::Unavailable,
                         has_sig: true,
+                        timelocks: Timelocks::default(),
+                        revealed,
                     }
                 } else {
                     // Throw away the most expensive ones
+                    let sig_revealed = Revealed {
+                        sigs: 1,
+                        ..Revealed::default()
+                    };
                     for _ in 0..sig_count - k {
                         let max_idx = sigs
                             .iter()
                             .enumerate()
-                            .max_by_key(|&(_, ref v)| v.len())
+                            .max_by_key(|&(_, ref v)| strategy.cost(v, &sig_revealed))
                             .unwrap()
                             .0;
-                        sigs[max_idx] = vec![];
+                        sigs.remove(max_idx);
                     }
 
                     Satisfaction {
@@ -743,41 +1362,60 @@ impl Satisfaction {
                             Witness::combine(acc, Witness::Stack(sig))
                         }),
                         has_sig: true,
+                        timelocks: Timelocks::default(),
+                        revealed,
                     }
                 }
             }
         }
     }
 
-    /// Produce a satisfaction
-    fn dissatisfy<Pk: MiniscriptKey + ToPublicKey, Sat: Satisfier<Pk>>(
-        term: &Terminal<Pk>,
-        stfr: &Sat,
-    ) -> Self {
+    /// Produce a dissatisfaction
+    pub fn dissatisfy<Pk, Sat, St>(term: &Terminal<Pk>, stfr: &Sat, strategy: &St) -> Self
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Sat: Satisfier<Pk>,
+        St: SatisfactionStrategy,
+    {
         match *term {
             Terminal::Pk(..) => Satisfaction {
                 stack: Witness::push_0(),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::PkH(ref pkh) => Satisfaction {
                 stack: Witness::combine(Witness::push_0(), Witness::pkh_public_key(stfr, pkh)),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed {
+                    pkh_keys: 1,
+                    ..Revealed::default()
+                },
             },
             Terminal::False => Satisfaction {
                 stack: Witness::empty(),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::True => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::Older(_) => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::After(_) => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::Sha256(_)
             | Terminal::Hash256(_)
@@ -785,69 +1423,291 @@ impl Satisfaction {
             | Terminal::Hash160(_) => Satisfaction {
                 stack: Witness::hash_dissatisfaction(),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::Alt(ref sub)
             | Terminal::Swap(ref sub)
             | Terminal::Check(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => Self::dissatisfy(&sub.node, stfr),
+            | Terminal::ZeroNotEqual(ref sub) => Self::dissatisfy(&sub.node, stfr, strategy),
             Terminal::DupIf(_) | Terminal::NonZero(_) => Satisfaction {
                 stack: Witness::push_0(),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::Verify(_) => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::AndV(ref v, ref other) => {
-                let vsat = Self::satisfy(&v.node, stfr);
-                let odissat = Self::dissatisfy(&other.node, stfr);
-                Satisfaction {
-                    stack: Witness::combine(odissat.stack, vsat.stack),
-                    has_sig: vsat.has_sig || odissat.has_sig,
-                }
+                let vsat = Self::satisfy(&v.node, stfr, strategy);
+                let odissat = Self::dissatisfy(&other.node, stfr, strategy);
+                Self::combine(odissat, vsat)
             }
             Terminal::AndB(ref l, ref r)
             | Terminal::OrB(ref l, ref r)
             | Terminal::OrD(ref l, ref r)
             | Terminal::AndOr(ref l, _, ref r) => {
-                let lnsat = Self::dissatisfy(&l.node, stfr);
-                let rnsat = Self::dissatisfy(&r.node, stfr);
-                Satisfaction {
-                    stack: Witness::combine(rnsat.stack, lnsat.stack),
-                    has_sig: rnsat.has_sig || lnsat.has_sig,
-                }
+                let lnsat = Self::dissatisfy(&l.node, stfr, strategy);
+                let rnsat = Self::dissatisfy(&r.node, stfr, strategy);
+                Self::combine(rnsat, lnsat)
             }
             Terminal::OrC(..) => Satisfaction {
                 stack: Witness::Unavailable,
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
             Terminal::OrI(ref l, ref r) => {
-                let lnsat = Self::dissatisfy(&l.node, stfr);
+                let lnsat = Self::dissatisfy(&l.node, stfr, strategy);
                 let dissat_1 = Satisfaction {
                     stack: Witness::combine(lnsat.stack, Witness::push_1()),
                     has_sig: lnsat.has_sig,
+                    timelocks: lnsat.timelocks,
+                    revealed: lnsat.revealed,
                 };
 
-                let rnsat = Self::dissatisfy(&r.node, stfr);
+                let rnsat = Self::dissatisfy(&r.node, stfr, strategy);
                 let dissat_2 = Satisfaction {
                     stack: Witness::combine(rnsat.stack, Witness::push_0()),
                     has_sig: rnsat.has_sig,
+                    timelocks: rnsat.timelocks,
+                    revealed: rnsat.revealed,
                 };
 
-                Self::minimum(dissat_1, dissat_2)
+                // If only one branch can be dissatisfied there is nothing to
+                // choose between, so this cannot be a malleability vector
+                match (&dissat_1.stack, &dissat_2.stack) {
+                    (&Witness::Unavailable, _) => dissat_2,
+                    (_, &Witness::Unavailable) => dissat_1,
+                    _ => Self::minimum(dissat_1, dissat_2, strategy),
+                }
             }
-            Terminal::Thresh(_, ref subs) => Satisfaction {
-                stack: subs.iter().fold(Witness::empty(), |acc, sub| {
-                    let nsat = Self::dissatisfy(&sub.node, stfr);
+            Terminal::Thresh(_, ref subs) => subs.iter().fold(
+                Satisfaction {
+                    stack: Witness::empty(),
+                    has_sig: false,
+                    timelocks: Timelocks::default(),
+                    revealed: Revealed::default(),
+                },
+                |acc, sub| {
+                    let nsat = Self::dissatisfy(&sub.node, stfr, strategy);
                     assert!(!nsat.has_sig);
-                    Witness::combine(nsat.stack, acc)
-                }),
-                has_sig: false,
-            },
+                    Self::combine(nsat, acc)
+                },
+            ),
             Terminal::ThreshM(k, _) => Satisfaction {
                 stack: Witness::Stack(vec![vec![]; k + 1]),
                 has_sig: false,
+                timelocks: Timelocks::default(),
+                revealed: Revealed::default(),
             },
         }
     }
 }
+
+/// A single piece of data which a satisfier was unable to provide, and
+/// which is needed (possibly together with others) to satisfy a script
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum Missing {
+    /// A signature with the given key
+    Sig(bitcoin::PublicKey),
+    /// A signature and public key matching the given key hash
+    PkhSig(hash160::Hash),
+    /// The preimage of a SHA256 hash
+    Sha256(sha256::Hash),
+    /// The preimage of a HASH256 hash
+    Hash256(sha256d::Hash),
+    /// The preimage of a RIPEMD160 hash
+    Ripemd160(ripemd160::Hash),
+    /// The preimage of a HASH160 hash
+    Hash160(hash160::Hash),
+    /// A relative locktime which has not been met
+    Older(RelLockTime),
+    /// An absolute locktime which has not been met
+    After(AbsLockTime),
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Missing::Sig(ref pk) => write!(f, "sig({})", pk),
+            Missing::PkhSig(ref h) => write!(f, "pkh_sig({})", h),
+            Missing::Sha256(ref h) => write!(f, "sha256({})", h),
+            Missing::Hash256(ref h) => write!(f, "hash256({})", h),
+            Missing::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            Missing::Hash160(ref h) => write!(f, "hash160({})", h),
+            Missing::Older(n) => write!(f, "older({})", n),
+            Missing::After(n) => write!(f, "after({})", n),
+        }
+    }
+}
+
+impl Missing {
+    /// Computes all minimal sets of items which, if made available to the
+    /// satisfier, would allow the fragment to be satisfied, i.e. no returned
+    /// set is a superset of another. Each set is sorted, and sets are
+    /// returned in order of increasing size.
+    ///
+    /// An empty list means the fragment can never be satisfied; a list
+    /// containing the empty set means no data is missing. Data needed only
+    /// to dissatisfy sub-fragments is assumed to be available, and
+    /// malleability is not taken into account, so a fragment may still be
+    /// unsatisfiable (without malleability) even if nothing is missing.
+    ///
+    /// The number of sets can grow exponentially with the width of `or`s
+    /// and thresholds, so this fails with `Error::DnfTooLarge` as soon as
+    /// any sub-fragment would need more than `max_sets` sets.
+    pub fn minimal_sets<Pk, Sat>(
+        term: &Terminal<Pk>,
+        stfr: &Sat,
+        max_sets: usize,
+    ) -> Result<Vec<Vec<Missing>>, Error>
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Sat: Satisfier<Pk>,
+    {
+        fn leaf(available: bool, missing: Missing) -> Vec<Vec<Missing>> {
+            if available {
+                vec![vec![]]
+            } else {
+                vec![vec![missing]]
+            }
+        }
+
+        let sets = match *term {
+            Terminal::Pk(ref pk) => leaf(
+                stfr.lookup_sig(pk).is_some(),
+                Missing::Sig(pk.to_public_key()),
+            ),
+            Terminal::PkH(ref pkh) => leaf(
+                stfr.lookup_pkh_sig(pkh).is_some(),
+                Missing::PkhSig(Pk::hash_to_hash160(pkh)),
+            ),
+            Terminal::After(t) => leaf(stfr.check_after(t), Missing::After(t)),
+            Terminal::Older(t) => leaf(stfr.check_older(t), Missing::Older(t)),
+            Terminal::Sha256(h) => leaf(stfr.lookup_sha256(h).is_some(), Missing::Sha256(h)),
+            Terminal::Hash256(h) => leaf(stfr.lookup_hash256(h).is_some(), Missing::Hash256(h)),
+            Terminal::Ripemd160(h) => {
+                leaf(stfr.lookup_ripemd160(h).is_some(), Missing::Ripemd160(h))
+            }
+            Terminal::Hash160(h) => leaf(stfr.lookup_hash160(h).is_some(), Missing::Hash160(h)),
+            Terminal::True => vec![vec![]],
+            Terminal::False => vec![],
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::DupIf(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => Self::minimal_sets(&sub.node, stfr, max_sets)?,
+            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
+                let subs = [
+                    Self::minimal_sets(&l.node, stfr, max_sets)?,
+                    Self::minimal_sets(&r.node, stfr, max_sets)?,
+                ];
+                capped_thresh_sets(2, &subs, max_sets).ok_or(Error::DnfTooLarge(max_sets))?
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => {
+                let subs = [
+                    Self::minimal_sets(&a.node, stfr, max_sets)?,
+                    Self::minimal_sets(&b.node, stfr, max_sets)?,
+                ];
+                let mut ret =
+                    capped_thresh_sets(2, &subs, max_sets).ok_or(Error::DnfTooLarge(max_sets))?;
+                ret.extend(Self::minimal_sets(&c.node, stfr, max_sets)?);
+                minimize_sets(ret)
+            }
+            Terminal::OrB(ref l, ref r)
+            | Terminal::OrD(ref l, ref r)
+            | Terminal::OrC(ref l, ref r)
+            | Terminal::OrI(ref l, ref r) => {
+                let mut ret = Self::minimal_sets(&l.node, stfr, max_sets)?;
+                ret.extend(Self::minimal_sets(&r.node, stfr, max_sets)?);
+                minimize_sets(ret)
+            }
+            Terminal::Thresh(k, ref subs) => {
+                let subs = subs
+                    .iter()
+                    .map(|s| Self::minimal_sets(&s.node, stfr, max_sets))
+                    .collect::<Result<Vec<_>, _>>()?;
+                capped_thresh_sets(k, &subs, max_sets).ok_or(Error::DnfTooLarge(max_sets))?
+            }
+            Terminal::ThreshM(k, ref keys) => {
+                let subs = keys
+                    .iter()
+                    .map(|pk| {
+                        leaf(
+                            stfr.lookup_sig(pk).is_some(),
+                            Missing::Sig(pk.to_public_key()),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                capped_thresh_sets(k, &subs, max_sets).ok_or(Error::DnfTooLarge(max_sets))?
+            }
+        };
+        if sets.len() > max_sets {
+            Err(Error::DnfTooLarge(max_sets))
+        } else {
+            Ok(sets)
+        }
+    }
+}
+
+/// Drops duplicate sets and sets which are supersets of others, and sorts
+/// the remainder by size
+pub(crate) fn minimize_sets<T: Ord>(mut sets: Vec<Vec<T>>) -> Vec<Vec<T>> {
+    sets.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    sets.dedup();
+
+    let mut ret: Vec<Vec<T>> = Vec::with_capacity(sets.len());
+    for set in sets {
+        if !ret.iter().any(|kept| kept.iter().all(|x| set.contains(x))) {
+            ret.push(set);
+        }
+    }
+    ret
+}
+
+/// Computes the sets needed to satisfy both of two fragments
+pub(crate) fn and_sets<T: Ord + Clone>(left: &[Vec<T>], right: &[Vec<T>]) -> Vec<Vec<T>> {
+    let mut ret = Vec::with_capacity(left.len() * right.len());
+    for l in left {
+        for r in right {
+            let mut set = l.clone();
+            set.extend(r.iter().cloned());
+            set.sort();
+            set.dedup();
+            ret.push(set);
+        }
+    }
+    minimize_sets(ret)
+}
+
+/// Computes the sets needed to satisfy `k` of the given fragments, or
+/// returns `None` if any intermediate expansion would need more than
+/// `max_sets` sets
+pub(crate) fn capped_thresh_sets<T: Ord + Clone>(
+    k: usize,
+    subs: &[Vec<Vec<T>>],
+    max_sets: usize,
+) -> Option<Vec<Vec<T>>> {
+    // `table[j]` holds the sets needed to satisfy `j` of the fragments
+    // considered so far
+    let mut table = vec![vec![]; k + 1];
+    table[0] = vec![vec![]];
+    for sub in subs {
+        for j in (1..k + 1).rev() {
+            if table[j - 1].len().saturating_mul(sub.len()) > max_sets {
+                return None;
+            }
+            let mut new = and_sets(&table[j - 1], sub);
+            new.append(&mut table[j]);
+            table[j] = minimize_sets(new);
+        }
+    }
+    table.pop()
+}
@@ -0,0 +1,165 @@
+// Miniscript
+// Written in 2020 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Timelocks
+//!
+//! Typed values for the arguments of `older` (`OP_CHECKSEQUENCEVERIFY`, see
+//! BIP 68 and BIP 112) and `after` (`OP_CHECKLOCKTIMEVERIFY`, see BIP 65).
+//! Both keep the exact consensus value that appears in the script, but know
+//! whether they are measured in blocks or in time, and compare against
+//! `nSequence`/`nLockTime` values the way consensus code does.
+//!
+
+use std::fmt;
+
+use Error;
+
+/// BIP 68 flag which disables the relative locktime meaning of `nSequence`
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// BIP 68 flag which makes the relative locktime time-based (in units of
+/// 512 seconds) rather than height-based
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// BIP 68 mask of the bits of `nSequence` which hold the locktime value
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+/// `nLockTime` values below this are block heights, others are timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// A relative timelock, the argument of an `older` fragment
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct RelLockTime(u32);
+
+impl RelLockTime {
+    /// Construct a relative timelock from the value used in script. Fails
+    /// if the BIP 68 disable flag is set or if the locktime value is zero
+    pub fn from_consensus(n: u32) -> Result<RelLockTime, Error> {
+        if n & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 || n & SEQUENCE_LOCKTIME_MASK == 0 {
+            Err(Error::InvalidRelativeLockTime(n))
+        } else {
+            Ok(RelLockTime(n))
+        }
+    }
+
+    /// Construct a relative timelock of `n` blocks
+    pub fn from_height(n: u16) -> Result<RelLockTime, Error> {
+        RelLockTime::from_consensus(n as u32)
+    }
+
+    /// Construct a relative timelock of `n` units of 512 seconds
+    pub fn from_512_second_intervals(n: u16) -> Result<RelLockTime, Error> {
+        RelLockTime::from_consensus(n as u32 | SEQUENCE_LOCKTIME_TYPE_FLAG)
+    }
+
+    /// The value as it appears in script
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    /// The number of blocks, or of 512-second intervals, of the lock
+    pub fn value(self) -> u16 {
+        (self.0 & SEQUENCE_LOCKTIME_MASK) as u16
+    }
+
+    /// Whether the lock is measured in blocks
+    pub fn is_height_locked(self) -> bool {
+        self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG == 0
+    }
+
+    /// Whether the lock is measured in units of 512 seconds
+    pub fn is_time_locked(self) -> bool {
+        !self.is_height_locked()
+    }
+
+    /// Whether the lock has the same unit as `other`, i.e. whether both
+    /// can be satisfied by a single `nSequence` value
+    pub fn is_same_unit(self, other: RelLockTime) -> bool {
+        self.is_height_locked() == other.is_height_locked()
+    }
+
+    /// Whether an input with the given `nSequence` satisfies the lock,
+    /// following the rules of `OP_CHECKSEQUENCEVERIFY`. The transaction
+    /// version must also be at least 2, which is not checked here.
+    pub fn is_satisfied_by(self, sequence: u32) -> bool {
+        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        self.is_implied_by(RelLockTime(sequence))
+    }
+
+    /// Whether any input satisfying `other` also satisfies `self`
+    pub fn is_implied_by(self, other: RelLockTime) -> bool {
+        self.is_same_unit(other) && self.value() <= other.value()
+    }
+}
+
+impl fmt::Display for RelLockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// An absolute timelock, the argument of an `after` fragment
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct AbsLockTime(u32);
+
+impl AbsLockTime {
+    /// Construct an absolute timelock from the value used in script. Fails
+    /// if the value is zero; any other `nLockTime` value is valid, since
+    /// `OP_CHECKLOCKTIMEVERIFY` accepts 5-byte script numbers
+    pub fn from_consensus(n: u32) -> Result<AbsLockTime, Error> {
+        if n == 0 {
+            Err(Error::InvalidAbsoluteLockTime(n))
+        } else {
+            Ok(AbsLockTime(n))
+        }
+    }
+
+    /// The value as it appears in script
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Whether the lock is a block height
+    pub fn is_block_height(self) -> bool {
+        self.0 < LOCKTIME_THRESHOLD
+    }
+
+    /// Whether the lock is a UNIX timestamp
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether the lock has the same unit as `other`, i.e. whether both
+    /// can be satisfied by a single `nLockTime` value
+    pub fn is_same_unit(self, other: AbsLockTime) -> bool {
+        self.is_block_height() == other.is_block_height()
+    }
+
+    /// Whether a transaction with the given `nLockTime` satisfies the lock,
+    /// following the rules of `OP_CHECKLOCKTIMEVERIFY`. The input must also
+    /// have a non-final `nSequence`, which is not checked here.
+    pub fn is_satisfied_by(self, lock_time: u32) -> bool {
+        self.is_implied_by(AbsLockTime(lock_time))
+    }
+
+    /// Whether any transaction satisfying `other` also satisfies `self`
+    pub fn is_implied_by(self, other: AbsLockTime) -> bool {
+        self.is_same_unit(other) && self.0 <= other.0
+    }
+}
+
+impl fmt::Display for AbsLockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
@@ -33,8 +33,6 @@ fn return_none<T>(_: usize) -> Option<T> {
 /// Detailed type of a typechecker error
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum ErrorKind {
-    /// Relative or absolute timelock had a time value of 0
-    ZeroTime,
     /// Passed a `z` arguement to a `d` wrapeer when `z` was expected
     NonZeroDupIf,
     /// Multisignature or threshold policy had a `k` value of 0
@@ -104,11 +102,6 @@ impl<Pk: MiniscriptKey> error::Error for Error<Pk> {
 impl<Pk: MiniscriptKey> fmt::Display for Error<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.error {
-            ErrorKind::ZeroTime => write!(
-                f,
-                "fragment «{}» represents a 0-valued timelock (use `1` instead)",
-                self.fragment,
-            ),
             ErrorKind::NonZeroDupIf => write!(
                 f,
                 "fragment «{}» represents needs to be `z`, needs to consume zero elements from the stack",
@@ -286,13 +279,13 @@ pub trait Property: Sized {
     /// Type property of a timelock
     fn from_time(t: u32) -> Self;
 
-    /// Type property of a relative timelock. Default implementation simply
+    /// Type property of an absolute timelock. Default implementation simply
     /// passes through to `from_time`
     fn from_after(t: u32) -> Self {
         Self::from_time(t)
     }
 
-    /// Type property of an absolute timelock. Default implementation simply
+    /// Type property of a relative timelock. Default implementation simply
     /// passes through to `from_time`
     fn from_older(t: u32) -> Self {
         Self::from_time(t)
@@ -407,25 +400,8 @@ pub trait Property: Sized {
                 }
                 Ok(Self::from_multi(k, pks.len()))
             }
-            Terminal::After(t) => {
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_after(t))
-            }
-            Terminal::Older(t) => {
-                // FIXME check if t > 2^31 - 1
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_older(t))
-            }
+            Terminal::After(t) => Ok(Self::from_after(t.to_consensus_u32())),
+            Terminal::Older(t) => Ok(Self::from_older(t.to_consensus_u32())),
             Terminal::Sha256(..) => Ok(Self::from_sha256()),
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
@@ -780,25 +756,8 @@ impl Property for Type {
                 }
                 Ok(Self::from_multi(k, pks.len()))
             }
-            Terminal::After(t) => {
-                // FIXME check if t > 2^31 - 1
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_after(t))
-            }
-            Terminal::Older(t) => {
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_older(t))
-            }
+            Terminal::After(t) => Ok(Self::from_after(t.to_consensus_u32())),
+            Terminal::Older(t) => Ok(Self::from_older(t.to_consensus_u32())),
             Terminal::Sha256(..) => Ok(Self::from_sha256()),
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
@@ -0,0 +1,434 @@
+// Miniscript
+// Written in 2020 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BOLT-3 Scripts
+//!
+//! The `to_local`, offered HTLC and received HTLC outputs of Lightning
+//! commitment transactions, both as concrete policies which can be
+//! compiled to Miniscript, and as the hand-optimized scripts mandated by
+//! BOLT 3.
+//!
+//! None of the BOLT-3 scripts are Miniscript, so `Miniscript::parse`
+//! rejects them and they can never be produced by the compiler:
+//! * `to_local` shares a single `OP_CHECKSIG` between the two branches of
+//!   an `OP_IF`, and drops the argument of `OP_CHECKSEQUENCEVERIFY` with
+//!   `OP_DROP` rather than `OP_VERIFY`
+//! * both HTLC scripts test the revocation key with
+//!   `OP_DUP OP_HASH160 <hash> OP_EQUAL OP_IF`, move witness elements
+//!   around the remote key with `OP_SWAP`, branch on `OP_SIZE` of a
+//!   witness element, and take a key from the stack for `OP_CHECKMULTISIG`
+//!
+//! Instead, `lift_script` recognizes exactly these three scripts and
+//! returns their semantic policy, which can be compared with the lifted
+//! policies of this module using `Semantic::is_equivalent`.
+//!
+//! In the offered HTLC the timeout, and in the received HTLC the preimage,
+//! are enforced by the pre-signed HTLC-timeout and HTLC-success
+//! transactions rather than by the script, so in both policies that path
+//! is just a signature from each of the HTLC keys. The scripts check the
+//! payment preimage with `OP_HASH160` against `RIPEMD160(payment_hash)`,
+//! so the policies use a `hash160` of that value rather than a `sha256`
+//! of the payment hash; both are satisfied by the same 32-byte preimages.
+//!
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::hashes::{hash160, ripemd160, sha256, Hash};
+use bitcoin::PublicKey;
+
+use miniscript::lex::{lex, Token, TokenIter};
+use policy::builder::{self, after, older, pk};
+use policy::{Concrete, Semantic};
+use {errstr, AbsLockTime, Error, MiniscriptKey, RelLockTime};
+
+/// Policy of the `to_local` output
+///
+/// Spending paths:
+/// * `revocation` signs, when the remote node punishes a revoked
+///   commitment
+/// * `local_delayed` signs once the output is `to_self_delay` blocks old
+///
+/// The delayed path is assumed 9 times as likely as a revocation.
+pub fn to_local<Pk: MiniscriptKey>(
+    revocation: Pk,
+    local_delayed: Pk,
+    to_self_delay: u32,
+) -> Result<Concrete<Pk>, Error> {
+    builder::or(vec![
+        (1, pk(revocation)),
+        (9, older(to_self_delay).and(pk(local_delayed))),
+    ])
+    .build()
+}
+
+/// Policy of an offered HTLC output
+///
+/// Spending paths:
+/// * `revocation` signs
+/// * `remote_htlc` and `local_htlc` sign the HTLC-timeout transaction
+/// * `remote_htlc` signs and reveals the payment preimage
+///
+/// A revocation is assumed 9 times less likely than the other paths,
+/// which are equally likely.
+pub fn offered_htlc<Pk: MiniscriptKey>(
+    revocation: Pk,
+    remote_htlc: Pk,
+    local_htlc: Pk,
+    payment_hash: sha256::Hash,
+) -> Result<Concrete<Pk>, Error> {
+    let timeout_or_preimage = pk(local_htlc).or(builder::hash160(payment_hash160(payment_hash)));
+    builder::or(vec![
+        (1, pk(revocation)),
+        (9, pk(remote_htlc).and(timeout_or_preimage)),
+    ])
+    .build()
+}
+
+/// Policy of a received HTLC output
+///
+/// Spending paths:
+/// * `revocation` signs
+/// * `remote_htlc` and `local_htlc` sign the HTLC-success transaction,
+///   which reveals the payment preimage
+/// * `remote_htlc` signs after the absolute block height `cltv_expiry`
+///
+/// A revocation is assumed 9 times less likely than the other paths,
+/// which are equally likely.
+pub fn received_htlc<Pk: MiniscriptKey>(
+    revocation: Pk,
+    remote_htlc: Pk,
+    local_htlc: Pk,
+    payment_hash: sha256::Hash,
+    cltv_expiry: u32,
+) -> Result<Concrete<Pk>, Error> {
+    let success = builder::hash160(payment_hash160(payment_hash)).and(pk(local_htlc));
+    builder::or(vec![
+        (1, pk(revocation)),
+        (9, pk(remote_htlc).and(success.or(after(cltv_expiry)))),
+    ])
+    .build()
+}
+
+/// The BOLT-3 `to_local` script
+pub fn to_local_script(
+    revocation: &PublicKey,
+    local_delayed: &PublicKey,
+    to_self_delay: u32,
+) -> Script {
+    Builder::new()
+        .push_opcode(opcodes::all::OP_IF)
+        .push_key(revocation)
+        .push_opcode(opcodes::all::OP_ELSE)
+        .push_int(to_self_delay as i64)
+        .push_opcode(opcodes::all::OP_CSV)
+        .push_opcode(opcodes::all::OP_DROP)
+        .push_key(local_delayed)
+        .push_opcode(opcodes::all::OP_ENDIF)
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .into_script()
+}
+
+/// The BOLT-3 offered HTLC script
+pub fn offered_htlc_script(
+    revocation: &PublicKey,
+    remote_htlc: &PublicKey,
+    local_htlc: &PublicKey,
+    payment_hash: sha256::Hash,
+) -> Script {
+    htlc_script_prefix(revocation, remote_htlc)
+        .push_opcode(opcodes::all::OP_NOTIF)
+        .push_opcode(opcodes::all::OP_DROP)
+        .push_int(2)
+        .push_opcode(opcodes::all::OP_SWAP)
+        .push_key(local_htlc)
+        .push_int(2)
+        .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+        .push_opcode(opcodes::all::OP_ELSE)
+        .push_opcode(opcodes::all::OP_HASH160)
+        .push_slice(&payment_hash160(payment_hash)[..])
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .push_opcode(opcodes::all::OP_ENDIF)
+        .push_opcode(opcodes::all::OP_ENDIF)
+        .into_script()
+}
+
+/// The BOLT-3 received HTLC script
+pub fn received_htlc_script(
+    revocation: &PublicKey,
+    remote_htlc: &PublicKey,
+    local_htlc: &PublicKey,
+    payment_hash: sha256::Hash,
+    cltv_expiry: u32,
+) -> Script {
+    htlc_script_prefix(revocation, remote_htlc)
+        .push_opcode(opcodes::all::OP_IF)
+        .push_opcode(opcodes::all::OP_HASH160)
+        .push_slice(&payment_hash160(payment_hash)[..])
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .push_int(2)
+        .push_opcode(opcodes::all::OP_SWAP)
+        .push_key(local_htlc)
+        .push_int(2)
+        .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+        .push_opcode(opcodes::all::OP_ELSE)
+        .push_opcode(opcodes::all::OP_DROP)
+        .push_int(cltv_expiry as i64)
+        .push_opcode(opcodes::all::OP_CLTV)
+        .push_opcode(opcodes::all::OP_DROP)
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .push_opcode(opcodes::all::OP_ENDIF)
+        .push_opcode(opcodes::all::OP_ENDIF)
+        .into_script()
+}
+
+/// Lift a BOLT-3 `to_local`, offered HTLC or received HTLC script into a
+/// semantic policy. Fails on any other script.
+pub fn lift_script(script: &Script) -> Result<Semantic<PublicKey>, Error> {
+    let mut tokens = lex(script)?;
+    // `TokenIter` reads backward
+    tokens.reverse();
+    let mut tokens = TokenIter::new(tokens);
+
+    let policy = match tokens.next() {
+        Some(Token::If) => {
+            let revocation = expect_key(&mut tokens)?;
+            expect(&mut tokens, Token::Else)?;
+            let delay = RelLockTime::from_consensus(expect_num(&mut tokens)?)?;
+            expect(&mut tokens, Token::CheckSequenceVerify)?;
+            expect(&mut tokens, Token::Drop)?;
+            let local_delayed = expect_key(&mut tokens)?;
+            expect(&mut tokens, Token::EndIf)?;
+            expect(&mut tokens, Token::CheckSig)?;
+            Semantic::Or(vec![
+                Semantic::KeyHash(revocation.to_pubkeyhash()),
+                Semantic::And(vec![
+                    Semantic::Older(delay),
+                    Semantic::KeyHash(local_delayed.to_pubkeyhash()),
+                ]),
+            ])
+        }
+        Some(Token::Dup) => {
+            expect(&mut tokens, Token::Hash160)?;
+            let revocation = expect_hash20(&mut tokens)?;
+            for tok in &[Token::Equal, Token::If, Token::CheckSig, Token::Else] {
+                expect(&mut tokens, *tok)?;
+            }
+            let remote_htlc = expect_key(&mut tokens)?;
+            for tok in &[Token::Swap, Token::Size, Token::Num(32), Token::Equal] {
+                expect(&mut tokens, *tok)?;
+            }
+            let htlc = match tokens.next() {
+                Some(Token::NotIf) => {
+                    expect(&mut tokens, Token::Drop)?;
+                    let local_htlc = expect_2of2(&mut tokens)?;
+                    expect(&mut tokens, Token::Else)?;
+                    let payment = expect_hashlock(&mut tokens)?;
+                    expect(&mut tokens, Token::CheckSig)?;
+                    Semantic::Or(vec![local_htlc, payment])
+                }
+                Some(Token::If) => {
+                    let payment = expect_hashlock(&mut tokens)?;
+                    let local_htlc = expect_2of2(&mut tokens)?;
+                    expect(&mut tokens, Token::Else)?;
+                    expect(&mut tokens, Token::Drop)?;
+                    let expiry = AbsLockTime::from_consensus(expect_num(&mut tokens)?)?;
+                    expect(&mut tokens, Token::CheckLockTimeVerify)?;
+                    expect(&mut tokens, Token::Drop)?;
+                    expect(&mut tokens, Token::CheckSig)?;
+                    Semantic::Or(vec![
+                        Semantic::And(vec![payment, local_htlc]),
+                        Semantic::After(expiry),
+                    ])
+                }
+                Some(tok) => return Err(Error::Unexpected(tok.to_string())),
+                None => return Err(errstr("end of script")),
+            };
+            expect(&mut tokens, Token::EndIf)?;
+            expect(&mut tokens, Token::EndIf)?;
+            Semantic::Or(vec![
+                Semantic::KeyHash(revocation),
+                Semantic::And(vec![Semantic::KeyHash(remote_htlc.to_pubkeyhash()), htlc]),
+            ])
+        }
+        Some(tok) => return Err(Error::Unexpected(tok.to_string())),
+        None => return Err(errstr("empty script")),
+    };
+
+    match tokens.next() {
+        None => Ok(policy.normalized()),
+        Some(tok) => Err(Error::Trailing(tok.to_string())),
+    }
+}
+
+/// The value BOLT-3 scripts compare `OP_HASH160` of the payment preimage
+/// with, `RIPEMD160(payment_hash)`
+fn payment_hash160(payment_hash: sha256::Hash) -> hash160::Hash {
+    let hash = ripemd160::Hash::hash(&payment_hash[..]);
+    hash160::Hash::from_slice(&hash[..]).expect("20 bytes")
+}
+
+/// Start of both HTLC scripts, up to the branch on the size of the top
+/// witness element
+fn htlc_script_prefix(revocation: &PublicKey, remote_htlc: &PublicKey) -> Builder {
+    Builder::new()
+        .push_opcode(opcodes::all::OP_DUP)
+        .push_opcode(opcodes::all::OP_HASH160)
+        .push_slice(&revocation.to_pubkeyhash()[..])
+        .push_opcode(opcodes::all::OP_EQUAL)
+        .push_opcode(opcodes::all::OP_IF)
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .push_opcode(opcodes::all::OP_ELSE)
+        .push_key(remote_htlc)
+        .push_opcode(opcodes::all::OP_SWAP)
+        .push_opcode(opcodes::all::OP_SIZE)
+        .push_int(32)
+        .push_opcode(opcodes::all::OP_EQUAL)
+}
+
+/// Read the next token, failing unless it is `tok`
+fn expect(tokens: &mut TokenIter, tok: Token) -> Result<(), Error> {
+    match tokens.next() {
+        Some(ref next) if *next == tok => Ok(()),
+        Some(next) => Err(Error::Unexpected(next.to_string())),
+        None => Err(errstr("end of script")),
+    }
+}
+
+/// Read a public key
+fn expect_key(tokens: &mut TokenIter) -> Result<PublicKey, Error> {
+    match tokens.next() {
+        Some(Token::Pubkey(pk)) => Ok(pk),
+        Some(next) => Err(Error::Unexpected(next.to_string())),
+        None => Err(errstr("end of script")),
+    }
+}
+
+/// Read a 20-byte hash
+fn expect_hash20(tokens: &mut TokenIter) -> Result<hash160::Hash, Error> {
+    match tokens.next() {
+        Some(Token::Hash20(hash)) => Ok(hash160::Hash::from_inner(hash)),
+        Some(next) => Err(Error::Unexpected(next.to_string())),
+        None => Err(errstr("end of script")),
+    }
+}
+
+/// Read a number
+fn expect_num(tokens: &mut TokenIter) -> Result<u32, Error> {
+    match tokens.next() {
+        Some(Token::Num(n)) => Ok(n),
+        Some(next) => Err(Error::Unexpected(next.to_string())),
+        None => Err(errstr("end of script")),
+    }
+}
+
+/// Read `OP_HASH160 <hash> OP_EQUALVERIFY`
+fn expect_hashlock(tokens: &mut TokenIter) -> Result<Semantic<PublicKey>, Error> {
+    expect(tokens, Token::Hash160)?;
+    let hash = expect_hash20(tokens)?;
+    expect(tokens, Token::Equal)?;
+    expect(tokens, Token::Verify)?;
+    Ok(Semantic::Hash160(hash))
+}
+
+/// Read `2 OP_SWAP <key> 2 OP_CHECKMULTISIG`, which takes the other key
+/// from the stack, and return the policy of the key in the script
+fn expect_2of2(tokens: &mut TokenIter) -> Result<Semantic<PublicKey>, Error> {
+    expect(tokens, Token::Num(2))?;
+    expect(tokens, Token::Swap)?;
+    let key = expect_key(tokens)?;
+    expect(tokens, Token::Num(2))?;
+    expect(tokens, Token::CheckMultiSig)?;
+    Ok(Semantic::KeyHash(key.to_pubkeyhash()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1;
+    use policy::Liftable;
+    use Miniscript;
+
+    fn keys() -> Vec<PublicKey> {
+        let secp = secp256k1::Secp256k1::signing_only();
+        (1..5)
+            .map(|i| PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(
+                    &secp,
+                    &secp256k1::SecretKey::from_slice(&[i; 32]).unwrap(),
+                ),
+                compressed: true,
+            })
+            .collect()
+    }
+
+    /// The BOLT-3 scripts with their policies
+    fn bolt3() -> Vec<(Script, Concrete<PublicKey>)> {
+        let keys = keys();
+        let payment_hash = sha256::Hash::hash(&[1; 32]);
+        vec![
+            (
+                to_local_script(&keys[0], &keys[1], 144),
+                to_local(keys[0], keys[1], 144).unwrap(),
+            ),
+            (
+                offered_htlc_script(&keys[0], &keys[2], &keys[3], payment_hash),
+                offered_htlc(keys[0], keys[2], keys[3], payment_hash).unwrap(),
+            ),
+            (
+                received_htlc_script(&keys[0], &keys[2], &keys[3], payment_hash, 600000),
+                received_htlc(keys[0], keys[2], keys[3], payment_hash, 600000).unwrap(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn lift_bolt3() {
+        for (script, policy) in bolt3() {
+            assert!(Miniscript::parse(&script).is_err());
+            let lifted = lift_script(&script).unwrap();
+            assert!(lifted.is_equivalent(&policy.lift()).unwrap());
+        }
+
+        // The received HTLC needs the preimage for the 2-of-2 path only
+        let keys = keys();
+        let payment_hash = sha256::Hash::hash(&[1; 32]);
+        let script = received_htlc_script(&keys[0], &keys[2], &keys[3], payment_hash, 600000);
+        let wrong = offered_htlc(keys[0], keys[2], keys[3], payment_hash).unwrap();
+        assert!(!lift_script(&script)
+            .unwrap()
+            .is_equivalent(&wrong.lift())
+            .unwrap());
+
+        // Scripts with anything added or missing are rejected
+        let mut bytes = script.to_bytes();
+        bytes.push(opcodes::all::OP_VERIFY.into_u8());
+        assert!(lift_script(&Script::from(bytes)).is_err());
+        let bytes = script.to_bytes();
+        assert!(lift_script(&Script::from(bytes[..bytes.len() - 1].to_vec())).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn compile_bolt3() {
+        for (script, policy) in bolt3() {
+            let ms = policy.compile().unwrap();
+            assert!(ms
+                .lift()
+                .is_equivalent(&lift_script(&script).unwrap())
+                .unwrap());
+            assert!(lift_script(&ms.encode()).is_err());
+        }
+    }
+}
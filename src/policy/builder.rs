@@ -0,0 +1,239 @@
+// Miniscript
+// Written in 2020 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Policy Builder
+//!
+//! Functions and macros to construct concrete policies in Rust code rather
+//! than by parsing strings. Errors, such as invalid timelocks, are carried
+//! along and reported by `Builder::build`, which also runs
+//! `Concrete::check_policy` on the result. The `policy_and!` and
+//! `policy_or!` macros check at compile time that they are given at least
+//! two subpolicies, and `policy_thresh!` at least one; whether its `k` is
+//! within range is only checked by `build`.
+//!
+//! ```rust
+//! #[macro_use]
+//! extern crate miniscript;
+//!
+//! use miniscript::policy::builder::{older, pk};
+//!
+//! fn main() {
+//!     let policy = pk("A".to_owned())
+//!         .and(older(144))
+//!         .or_with(9, pk("B".to_owned()))
+//!         .build()
+//!         .unwrap();
+//!     assert_eq!(policy.to_string(), "or(1@and(pk(A),older(144)),9@pk(B))");
+//!
+//!     let (a, b, c) = ("A".to_owned(), "B".to_owned(), "C".to_owned());
+//!     let policy = policy_thresh!(2; pk(a), pk(b), pk(c)).build().unwrap();
+//!     assert_eq!(policy.to_string(), "thresh(2,pk(A),pk(B),pk(C))");
+//! }
+//! ```
+//!
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+
+use policy::Concrete;
+use {AbsLockTime, Error, MiniscriptKey, RelLockTime};
+
+/// A concrete policy under construction, or the first error encountered
+/// while constructing it
+#[derive(Debug)]
+pub struct Builder<Pk: MiniscriptKey>(Result<Concrete<Pk>, Error>);
+
+impl<Pk: MiniscriptKey> Builder<Pk> {
+    /// Require both this policy and `other`
+    pub fn and(self, other: Builder<Pk>) -> Builder<Pk> {
+        and(vec![self, other])
+    }
+
+    /// Require either this policy or `other`, both equally likely
+    pub fn or(self, other: Builder<Pk>) -> Builder<Pk> {
+        self.or_with(1, other)
+    }
+
+    /// Require either this policy or `other`, the latter being `prob`
+    /// times as likely to be used
+    pub fn or_with(self, prob: usize, other: Builder<Pk>) -> Builder<Pk> {
+        or(vec![(1, self), (prob, other)])
+    }
+
+    /// Finish constructing the policy, and check it with
+    /// `Concrete::check_policy`
+    pub fn build(self) -> Result<Concrete<Pk>, Error> {
+        let policy = self.0?;
+        policy.check_policy()?;
+        Ok(policy)
+    }
+}
+
+impl<Pk: MiniscriptKey> From<Concrete<Pk>> for Builder<Pk> {
+    fn from(policy: Concrete<Pk>) -> Builder<Pk> {
+        Builder(Ok(policy))
+    }
+}
+
+/// Require a signature with the key `pk`
+pub fn pk<Pk: MiniscriptKey>(pk: Pk) -> Builder<Pk> {
+    Builder(Ok(Concrete::Key(pk)))
+}
+
+/// Require the absolute locktime `n`, as a block height or timestamp
+pub fn after<Pk: MiniscriptKey>(n: u32) -> Builder<Pk> {
+    Builder(AbsLockTime::from_consensus(n).map(Concrete::After))
+}
+
+/// Require the relative locktime `n`, as encoded in `nSequence`
+pub fn older<Pk: MiniscriptKey>(n: u32) -> Builder<Pk> {
+    Builder(RelLockTime::from_consensus(n).map(Concrete::Older))
+}
+
+/// Require the preimage of a SHA256 hash
+pub fn sha256<Pk: MiniscriptKey>(hash: sha256::Hash) -> Builder<Pk> {
+    Builder(Ok(Concrete::Sha256(hash)))
+}
+
+/// Require the preimage of a SHA256d hash
+pub fn hash256<Pk: MiniscriptKey>(hash: sha256d::Hash) -> Builder<Pk> {
+    Builder(Ok(Concrete::Hash256(hash)))
+}
+
+/// Require the preimage of a RIPEMD160 hash
+pub fn ripemd160<Pk: MiniscriptKey>(hash: ripemd160::Hash) -> Builder<Pk> {
+    Builder(Ok(Concrete::Ripemd160(hash)))
+}
+
+/// Require the preimage of a HASH160 hash
+pub fn hash160<Pk: MiniscriptKey>(hash: hash160::Hash) -> Builder<Pk> {
+    Builder(Ok(Concrete::Hash160(hash)))
+}
+
+/// Require all of `subs`
+pub fn and<Pk: MiniscriptKey>(subs: Vec<Builder<Pk>>) -> Builder<Pk> {
+    Builder(
+        subs.into_iter()
+            .map(|sub| sub.0)
+            .collect::<Result<_, _>>()
+            .map(Concrete::And),
+    )
+}
+
+/// Require one of `subs`, each with its relative probability
+pub fn or<Pk: MiniscriptKey>(subs: Vec<(usize, Builder<Pk>)>) -> Builder<Pk> {
+    Builder(
+        subs.into_iter()
+            .map(|(prob, sub)| sub.0.map(|sub| (prob, sub)))
+            .collect::<Result<_, _>>()
+            .map(Concrete::Or),
+    )
+}
+
+/// Require `k` of `subs`
+pub fn thresh<Pk: MiniscriptKey>(k: usize, subs: Vec<Builder<Pk>>) -> Builder<Pk> {
+    Builder(
+        subs.into_iter()
+            .map(|sub| sub.0)
+            .collect::<Result<_, _>>()
+            .map(|subs| Concrete::Threshold(k, subs)),
+    )
+}
+
+/// Require a subset of `subs` whose weights add up to at least `k`
+pub fn wthresh<Pk: MiniscriptKey>(k: usize, subs: Vec<(usize, Builder<Pk>)>) -> Builder<Pk> {
+    Builder(
+        subs.into_iter()
+            .map(|(weight, sub)| sub.0.map(|sub| (weight, sub)))
+            .collect::<Result<_, _>>()
+            .map(|subs| Concrete::WeightedThreshold(k, subs)),
+    )
+}
+
+/// Require all of at least two subpolicies, as
+/// `policy_and!(pk(a), older(144))`
+#[macro_export]
+macro_rules! policy_and {
+    ($sub:expr, $($subs:expr),+ $(,)*) => {
+        $crate::policy::builder::and(vec![$sub, $($subs),+])
+    };
+}
+
+/// Require one of at least two subpolicies, each with its relative
+/// probability, as `policy_or!(9 => pk(a), 1 => pk(b))`
+#[macro_export]
+macro_rules! policy_or {
+    ($prob:expr => $sub:expr, $($probs:expr => $subs:expr),+ $(,)*) => {
+        $crate::policy::builder::or(vec![($prob, $sub), $(($probs, $subs)),+])
+    };
+}
+
+/// Require `k` of at least one subpolicy, as
+/// `policy_thresh!(2; pk(a), pk(b), pk(c))`. A `k` larger than the number
+/// of subpolicies is reported by `Builder::build`.
+#[macro_export]
+macro_rules! policy_thresh {
+    ($k:expr; $($subs:expr),+ $(,)*) => {
+        $crate::policy::builder::thresh($k, vec![$($subs),+])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy::concrete::PolicyError;
+
+    fn key(name: &str) -> Builder<String> {
+        pk(name.to_owned())
+    }
+
+    #[test]
+    fn build() {
+        let policy = key("A").and(older(144)).or_with(9, key("B"));
+        assert_eq!(
+            policy.build().unwrap().to_string(),
+            "or(1@and(pk(A),older(144)),9@pk(B))"
+        );
+
+        let policy = policy_or!(
+            9 => key("A"),
+            1 => policy_and!(key("B"), after(100), policy_thresh!(1; key("C"), key("D"))),
+        );
+        assert_eq!(
+            policy.build().unwrap().to_string(),
+            "or(9@pk(A),1@and(pk(B),after(100),thresh(1,pk(C),pk(D))))"
+        );
+
+        let policy = wthresh(3, vec![(2, key("A")), (1, key("B")), (1, key("C"))]);
+        assert_eq!(
+            policy.build().unwrap().to_string(),
+            "wthresh(3,2@pk(A),1@pk(B),1@pk(C))"
+        );
+    }
+
+    #[test]
+    fn build_errors() {
+        match key("A").and(older(0)).build() {
+            Err(Error::InvalidRelativeLockTime(0)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match policy_thresh!(3; key("A"), key("B")).build() {
+            Err(Error::PolicyError(PolicyError::IncorrectThresh(3, 2))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match key("A").or(key("A")).build() {
+            Err(Error::PolicyError(PolicyError::DuplicatePubKeys)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
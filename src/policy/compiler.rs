@@ -17,12 +17,14 @@
 //! Optimizing compiler from concrete policies to Miniscript
 //!
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::{cmp, error, f64, fmt};
 
 use miniscript::types::extra_props::MAX_OPS_PER_SCRIPT;
 use miniscript::types::{self, ErrorKind, ExtData, Property, Type};
-use policy::Concrete;
+use policy::concrete::PolicyError;
+use policy::{Concrete, Semantic};
 use std::collections::vec_deque::VecDeque;
 use std::hash;
 use std::sync::Arc;
@@ -44,15 +46,29 @@ impl Ord for OrdF64 {
 /// Detailed Error type for Compiler
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum CompilerError {
-    /// Compiler has non-safe input policy.
+    /// Compiler has non-safe input policy. The responsible sub-policies
+    /// are given by `Concrete::safety_issues`.
     TopLevelNonSafe,
     /// Non-Malleable compilation  does exists for the given sub-policy.
+    /// The responsible sub-policies are given by `Concrete::safety_issues`.
     ImpossibleNonMalleableCompilation,
     /// Atleast one satisfaction path in the optimal Miniscript has opcodes
     /// more than `MAX_OPS_PER_SCRIPT`(201). However, there may exist other
-    /// miniscripts which are under `MAX_OPS_PER_SCRIPT` but the compiler
-    /// currently does not find them.
+    /// miniscripts which are under the limit but the compiler currently
+    /// does not find them.
     MaxOpCountExceeded,
+    /// Every compilation the compiler found is larger than the output type
+    /// allows, e.g. more than 520 bytes for a P2SH redeem script
+    ScriptSizeExceeded,
+    /// A threshold with more subpolicies than `multi` allows, given as
+    /// `(k, n)`, has no compilation as `thresh`, or split into `and`s or
+    /// `or`s, within the opcount and script size limits. It must be split
+    /// into smaller thresholds by hand.
+    ThresholdTooLarge(usize, usize),
+    /// The policy fails `check_policy`
+    PolicyError(PolicyError),
+    /// The policy has uncompressed keys, which segwit outputs do not allow
+    UncompressedKeyInSegwit,
 }
 
 impl error::Error for CompilerError {
@@ -78,10 +94,31 @@ impl fmt::Display for CompilerError {
                 "Atleast one spending path has more op codes executed than \
                  MAX_OPS_PER_SCRIPT",
             ),
+            CompilerError::ScriptSizeExceeded => {
+                f.write_str("The script is too large for its output type")
+            }
+            CompilerError::ThresholdTooLarge(k, n) => write!(
+                f,
+                "Threshold {} of {} has more than {} subpolicies and cannot be \
+                 compiled within MAX_OPS_PER_SCRIPT and the script size limit; \
+                 split it into smaller thresholds",
+                k, n, MAX_PUBKEYS_PER_MULTISIG,
+            ),
+            CompilerError::PolicyError(ref e) => fmt::Display::fmt(e, f),
+            CompilerError::UncompressedKeyInSegwit => {
+                f.write_str("Segwit outputs cannot have uncompressed keys")
+            }
         }
     }
 }
 
+#[doc(hidden)]
+impl From<PolicyError> for CompilerError {
+    fn from(e: PolicyError) -> CompilerError {
+        CompilerError::PolicyError(e)
+    }
+}
+
 /// Hash required for using OrdF64 as key for hashmap
 impl hash::Hash for OrdF64 {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
@@ -128,6 +165,260 @@ impl CompilationKey {
     }
 }
 
+/// Maximum size of any script, enforced by consensus
+const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum size of a P2SH redeem script, which must fit in a single push
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum size of a standard P2WSH witness script
+const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+/// Maximum number of keys in a `multi`, enforced by consensus
+const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Maximum number of sat and dissat probabilities each subpolicy is
+/// compiled for
+const MAX_CONTEXTS: usize = 16;
+
+/// Output types a policy can be compiled for
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum DescriptorType {
+    /// Bare output, with the script in the scriptpubkey
+    Bare,
+    /// P2SH output. The standardness limit of 15 signature operations per
+    /// redeem script is not enforced by the compiler.
+    Sh,
+    /// P2WSH output
+    Wsh,
+    /// P2WSH output wrapped in P2SH
+    ShWsh,
+}
+
+impl DescriptorType {
+    /// Whether outputs of this type use segwit, and so require compressed
+    /// keys
+    pub fn is_segwit(self) -> bool {
+        match self {
+            DescriptorType::Bare | DescriptorType::Sh => false,
+            DescriptorType::Wsh | DescriptorType::ShWsh => true,
+        }
+    }
+
+    /// Limits a compilation for this output type must respect
+    fn limits(self) -> ScriptLimits {
+        ScriptLimits {
+            max_script_size: match self {
+                DescriptorType::Bare => MAX_SCRIPT_SIZE,
+                DescriptorType::Sh => MAX_SCRIPT_ELEMENT_SIZE,
+                DescriptorType::Wsh | DescriptorType::ShWsh => MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+            },
+            max_ops: MAX_OPS_PER_SCRIPT,
+        }
+    }
+}
+
+/// Which cost of satisfying a compilation the compiler minimizes
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CostModel {
+    /// The cost averaged over all spending paths, weighted by the
+    /// probabilities given in the policy
+    Expected,
+    /// The cost of the most expensive spending path
+    WorstCase,
+}
+
+/// Parameters of the cost the compiler minimizes, which is the size of the
+/// script times `script_weight` plus the size of the satisfaction times
+/// `witness_weight`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CompilerParams {
+    /// Relative cost of a byte of script. Increase it when the output is
+    /// created more often than it is spent, or when the script is not in
+    /// the witness.
+    pub script_weight: f64,
+    /// Relative cost of a byte of satisfaction
+    pub witness_weight: f64,
+    /// Size of a signature including its sighash byte and push opcode;
+    /// 73 bytes at most, 72 when signing with low-R grinding
+    pub sig_size: usize,
+    /// Whether to minimize the expected or the worst-case satisfaction cost
+    pub cost_model: CostModel,
+}
+
+impl Default for CompilerParams {
+    fn default() -> CompilerParams {
+        CompilerParams {
+            script_weight: 1.0,
+            witness_weight: 1.0,
+            sig_size: 73,
+            cost_model: CostModel::Expected,
+        }
+    }
+}
+
+/// Limits of the script context a policy is compiled for. Compilations
+/// exceeding them are discarded while searching.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct ScriptLimits {
+    /// Maximum size of the script in bytes
+    max_script_size: usize,
+    /// Maximum number of non-push opcodes executed
+    max_ops: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> ScriptLimits {
+        DescriptorType::Bare.limits()
+    }
+}
+
+/// Which of the `ScriptLimits` compilations of a policy were discarded for
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+struct Exceeded {
+    /// Some compilation had too many opcodes
+    max_ops: bool,
+    /// Some compilation had too large a script
+    max_script_size: bool,
+}
+
+/// A (sub)policy along with the id it is interned under, and its interned
+/// subpolicies. Structurally equal subpolicies share an id, so the cache can
+/// be keyed on ids rather than on (clones of) the subpolicies themselves.
+struct PolicyNode<'a, Pk: 'a + MiniscriptKey> {
+    id: usize,
+    policy: &'a Concrete<Pk>,
+    subs: Vec<PolicyNode<'a, Pk>>,
+}
+
+/// What a policy is interned by: leaves by value, and other fragments by
+/// the ids of their interned subpolicies
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum InternKey<Pk: MiniscriptKey> {
+    Leaf(Concrete<Pk>),
+    And(Vec<usize>),
+    Or(Vec<(usize, usize)>),
+    Threshold(usize, Vec<usize>),
+}
+
+/// Cache of the best compilations of each interned (sub)policy for given
+/// sat and dissat probabilities, along with the limits they all respect and
+/// the parameters of the cost they minimize
+struct PolicyCache<Pk: MiniscriptKey> {
+    limits: ScriptLimits,
+    /// The limits compilations of the policy currently being compiled were
+    /// discarded for, not counting those of its subpolicies
+    exceeded: Cell<Exceeded>,
+    params: CompilerParams,
+    ids: HashMap<InternKey<Pk>, usize>,
+    /// Maximum number of sat and dissat probabilities each interned policy
+    /// is compiled for, `MAX_CONTEXTS` unless a test lifts the cap
+    max_contexts: usize,
+    map: HashMap<(usize, OrdF64, Option<OrdF64>), HashMap<CompilationKey, AstElemExt<Pk>>>,
+    /// The sat and dissat probabilities each interned policy was compiled for
+    contexts: HashMap<usize, Vec<(OrdF64, Option<OrdF64>)>>,
+}
+
+impl<Pk: MiniscriptKey> PolicyCache<Pk> {
+    fn new(limits: ScriptLimits, params: CompilerParams) -> PolicyCache<Pk> {
+        PolicyCache {
+            limits: limits,
+            exceeded: Cell::new(Exceeded::default()),
+            params: params,
+            ids: HashMap::new(),
+            max_contexts: MAX_CONTEXTS,
+            map: HashMap::new(),
+            contexts: HashMap::new(),
+        }
+    }
+
+    /// Cache the compilations of an interned policy
+    fn insert(
+        &mut self,
+        id: usize,
+        sat_prob: OrdF64,
+        dissat_prob: Option<OrdF64>,
+        compilations: HashMap<CompilationKey, AstElemExt<Pk>>,
+    ) {
+        self.contexts
+            .entry(id)
+            .or_insert(vec![])
+            .push((sat_prob, dissat_prob));
+        self.map.insert((id, sat_prob, dissat_prob), compilations);
+    }
+
+    /// The number of sat and dissat probabilities a policy is compiled for
+    /// grows exponentially with its depth. Once an interned policy has been
+    /// compiled for `max_contexts` of them, return the compilations for the
+    /// closest ones instead, keyed as if they were for the given ones.
+    fn closest_compilations(
+        &self,
+        id: usize,
+        sat_prob: f64,
+        dissat_prob: Option<f64>,
+    ) -> Option<HashMap<CompilationKey, AstElemExt<Pk>>> {
+        let contexts = self.contexts.get(&id)?;
+        if contexts.len() < self.max_contexts {
+            return None;
+        }
+        let log_dist =
+            |x: f64, y: f64| (x.max(f64::MIN_POSITIVE).ln() - y.max(f64::MIN_POSITIVE).ln()).abs();
+        let closest = contexts
+            .iter()
+            .filter_map(|&(sat, dissat)| match (dissat, dissat_prob) {
+                (None, None) => Some((OrdF64(log_dist(sat.0, sat_prob)), (sat, dissat))),
+                (Some(dissat), Some(q)) => Some((
+                    OrdF64(log_dist(sat.0, sat_prob) + log_dist(dissat.0, q)),
+                    (sat, Some(dissat)),
+                )),
+                _ => None,
+            })
+            .min_by_key(|&(dist, _)| dist)?
+            .1;
+        Some(
+            self.map[&(id, closest.0, closest.1)]
+                .iter()
+                .map(|(key, elem)| {
+                    let key = CompilationKey {
+                        dissat_prob: dissat_prob.map(OrdF64),
+                        ..*key
+                    };
+                    (key, elem.clone())
+                })
+                .collect(),
+        )
+    }
+
+    /// Intern a policy and all of its subpolicies
+    fn intern<'a>(&mut self, policy: &'a Concrete<Pk>) -> PolicyNode<'a, Pk> {
+        let (key, subs) = match *policy {
+            Concrete::And(ref subs) => {
+                let subs: Vec<_> = subs.iter().map(|sub| self.intern(sub)).collect();
+                (InternKey::And(subs.iter().map(|n| n.id).collect()), subs)
+            }
+            Concrete::Or(ref subs) => {
+                let nodes: Vec<_> = subs.iter().map(|&(_, ref sub)| self.intern(sub)).collect();
+                let key = subs
+                    .iter()
+                    .zip(nodes.iter())
+                    .map(|(&(prob, _), n)| (prob, n.id))
+                    .collect();
+                (InternKey::Or(key), nodes)
+            }
+            Concrete::Threshold(k, ref subs) => {
+                let subs: Vec<_> = subs.iter().map(|sub| self.intern(sub)).collect();
+                (
+                    InternKey::Threshold(k, subs.iter().map(|n| n.id).collect()),
+                    subs,
+                )
+            }
+            ref leaf => (InternKey::Leaf(leaf.clone()), vec![]),
+        };
+        let next_id = self.ids.len();
+        PolicyNode {
+            id: *self.ids.entry(key).or_insert(next_id),
+            policy: policy,
+            subs: subs,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct CompilerExtData {
     /// If this node is the direct child of a disjunction, this field must
@@ -141,6 +432,9 @@ struct CompilerExtData {
     /// (total length of all witness pushes, plus their own length prefixes)
     /// for fragments that can be dissatisfied without failing the script.
     dissat_cost: Option<f64>,
+    /// The number of bytes needed to satisfy the fragment in segwit format
+    /// using its most expensive satisfaction
+    max_sat_cost: f64,
 }
 
 impl Property for CompilerExtData {
@@ -154,6 +448,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: f64::MAX,
             dissat_cost: Some(0.0),
+            max_sat_cost: f64::MAX,
         }
     }
 
@@ -162,6 +457,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 73.0,
             dissat_cost: Some(1.0),
+            max_sat_cost: 73.0,
         }
     }
 
@@ -170,6 +466,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 73.0 + 34.0,
             dissat_cost: Some(1.0 + 34.0),
+            max_sat_cost: 73.0 + 34.0,
         }
     }
 
@@ -178,6 +475,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 1.0 + 73.0 * k as f64,
             dissat_cost: Some(1.0 * (k + 1) as f64),
+            max_sat_cost: 1.0 + 73.0 * k as f64,
         }
     }
 
@@ -186,6 +484,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 33.0,
             dissat_cost: Some(33.0),
+            max_sat_cost: 33.0,
         }
     }
 
@@ -194,6 +493,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 0.0,
             dissat_cost: None,
+            max_sat_cost: 0.0,
         }
     }
 
@@ -202,6 +502,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -210,6 +511,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -218,6 +520,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -226,6 +529,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 2.0 + self.sat_cost,
             dissat_cost: Some(1.0),
+            max_sat_cost: 2.0 + self.max_sat_cost,
         })
     }
 
@@ -234,6 +538,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: None,
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -242,6 +547,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: Some(1.0),
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -250,6 +556,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -258,6 +565,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: None,
+            max_sat_cost: self.max_sat_cost,
         })
     }
 
@@ -271,6 +579,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 2.0 + self.sat_cost,
             dissat_cost: Some(1.0),
+            max_sat_cost: 2.0 + self.max_sat_cost,
         })
     }
 
@@ -279,6 +588,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 1.0 + self.sat_cost,
             dissat_cost: Some(2.0),
+            max_sat_cost: 1.0 + self.max_sat_cost,
         })
     }
 
@@ -290,6 +600,7 @@ impl Property for CompilerExtData {
                 (Some(l), Some(r)) => Some(l + r),
                 _ => None,
             },
+            max_sat_cost: left.max_sat_cost + right.max_sat_cost,
         })
     }
 
@@ -298,6 +609,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: left.sat_cost + right.sat_cost,
             dissat_cost: None,
+            max_sat_cost: left.max_sat_cost + right.max_sat_cost,
         })
     }
 
@@ -313,6 +625,8 @@ impl Property for CompilerExtData {
             sat_cost: lprob * (l.sat_cost + r.dissat_cost.unwrap())
                 + rprob * (r.sat_cost + l.dissat_cost.unwrap()),
             dissat_cost: Some(l.dissat_cost.unwrap() + r.dissat_cost.unwrap()),
+            max_sat_cost: (l.max_sat_cost + r.dissat_cost.unwrap())
+                .max(r.max_sat_cost + l.dissat_cost.unwrap()),
         })
     }
 
@@ -327,6 +641,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: lprob * l.sat_cost + rprob * (r.sat_cost + l.dissat_cost.unwrap()),
             dissat_cost: r.dissat_cost.map(|rd| l.dissat_cost.unwrap() + rd),
+            max_sat_cost: l.max_sat_cost.max(r.max_sat_cost + l.dissat_cost.unwrap()),
         })
     }
 
@@ -341,6 +656,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: lprob * l.sat_cost + rprob * (r.sat_cost + l.dissat_cost.unwrap()),
             dissat_cost: None,
+            max_sat_cost: l.max_sat_cost.max(r.max_sat_cost + l.dissat_cost.unwrap()),
         })
     }
 
@@ -367,6 +683,7 @@ impl Property for CompilerExtData {
             } else {
                 None
             },
+            max_sat_cost: (2.0 + l.max_sat_cost).max(1.0 + r.max_sat_cost),
         })
     }
 
@@ -390,6 +707,7 @@ impl Property for CompilerExtData {
             } else {
                 None
             },
+            max_sat_cost: (a.max_sat_cost + b.max_sat_cost).max(adis + c.max_sat_cost),
         })
     }
 
@@ -398,6 +716,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: a.sat_cost + b.sat_cost,
             dissat_cost: a.dissat_cost,
+            max_sat_cost: a.max_sat_cost + b.max_sat_cost,
         })
     }
 
@@ -408,19 +727,57 @@ impl Property for CompilerExtData {
         let k_over_n = k as f64 / n as f64;
         let mut sat_cost = 0.0;
         let mut dissat_cost = 0.0;
+        // Extra cost of satisfying rather than dissatisfying each subexpression
+        let mut max_sat_diffs = Vec::with_capacity(n);
         for i in 0..n {
             let sub = sub_ck(i)?;
             sat_cost += sub.sat_cost;
             dissat_cost += sub.dissat_cost.unwrap();
+            max_sat_diffs.push(sub.max_sat_cost - sub.dissat_cost.unwrap());
         }
+        max_sat_diffs.sort_by(|a, b| b.partial_cmp(a).unwrap());
         Ok(CompilerExtData {
             branch_prob: None,
             sat_cost: sat_cost * k_over_n + dissat_cost * (1.0 - k_over_n),
             dissat_cost: Some(dissat_cost),
+            max_sat_cost: dissat_cost + max_sat_diffs[..k].iter().sum::<f64>(),
         })
     }
 }
 
+/// Computes the `CompilerExtData` of a fragment from that of its children,
+/// with the cost of signatures given by `params`
+fn compiler_ext_data<Pk, C>(
+    term: &Terminal<Pk>,
+    params: &CompilerParams,
+    child: C,
+) -> Option<CompilerExtData>
+where
+    Pk: MiniscriptKey,
+    C: FnMut(usize) -> Option<CompilerExtData>,
+{
+    if let Terminal::True = *term {
+        // only appears in casts, whose data is computed directly
+        return Some(CompilerExtData {
+            branch_prob: None,
+            sat_cost: 0.0,
+            dissat_cost: None,
+            max_sat_cost: 0.0,
+        });
+    }
+    let mut data = CompilerExtData::type_check(term, child).ok()?;
+    // `CompilerExtData` assumes 73-byte signatures
+    let n_sigs = match *term {
+        Terminal::Pk(..) | Terminal::PkH(..) => 1,
+        Terminal::ThreshM(k, _) => k,
+        _ => 0,
+    };
+    let extra_sig_cost = (params.sig_size as f64 - 73.0) * n_sigs as f64;
+    data.sat_cost += extra_sig_cost;
+    data.max_sat_cost += extra_sig_cost;
+    Some(data)
+}
+
 /// Miniscript AST fragment with additional data needed by the compiler
 #[derive(Clone, Debug)]
 struct AstElemExt<Pk: MiniscriptKey> {
@@ -434,22 +791,27 @@ impl<Pk: MiniscriptKey> AstElemExt<Pk> {
     /// Compute a 1-dimensional cost, given a probability of satisfaction
     /// and a probability of dissatisfaction; if `dissat_prob` is `None`
     /// then it is assumed that dissatisfaction never occurs
-    fn cost_1d(&self, sat_prob: f64, dissat_prob: Option<f64>) -> f64 {
-        self.ms.ext.pk_cost as f64
-            + self.comp_ext_data.sat_cost * sat_prob
-            + match (dissat_prob, self.comp_ext_data.dissat_cost) {
-                (Some(prob), Some(cost)) => prob * cost,
-                (Some(_), None) => f64::INFINITY,
-                (None, Some(_)) => 0.0,
-                (None, None) => 0.0,
-            }
+    fn cost_1d(&self, sat_prob: f64, dissat_prob: Option<f64>, params: &CompilerParams) -> f64 {
+        let sat_cost = match params.cost_model {
+            CostModel::Expected => self.comp_ext_data.sat_cost,
+            CostModel::WorstCase => self.comp_ext_data.max_sat_cost,
+        };
+        self.ms.ext.pk_cost as f64 * params.script_weight
+            + params.witness_weight
+                * (sat_cost * sat_prob
+                    + match (dissat_prob, self.comp_ext_data.dissat_cost) {
+                        (Some(prob), Some(cost)) => prob * cost,
+                        (Some(_), None) => f64::INFINITY,
+                        (None, Some(_)) => 0.0,
+                        (None, None) => 0.0,
+                    })
     }
 }
 
 impl<Pk: MiniscriptKey> AstElemExt<Pk> where {
-    fn terminal(ast: Terminal<Pk>) -> AstElemExt<Pk> {
+    fn terminal(ast: Terminal<Pk>, params: &CompilerParams) -> AstElemExt<Pk> {
         AstElemExt {
-            comp_ext_data: CompilerExtData::type_check(&ast, |_| None).unwrap(),
+            comp_ext_data: compiler_ext_data(&ast, params, |_| None).unwrap(),
             ms: Arc::new(Miniscript::from_ast(ast).expect("Terminal creation must always succeed")),
         }
     }
@@ -628,19 +990,35 @@ fn insert_elem<Pk: MiniscriptKey>(
     elem: AstElemExt<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
+    limits: ScriptLimits,
+    exceeded: &Cell<Exceeded>,
+    params: &CompilerParams,
 ) -> bool {
     // return malleable types directly. If a elem is malleable, all the casts
     // to it are also going to be malleable
     if !elem.ms.ty.mall.non_malleable {
         return false;
     }
+    // elements exceeding the limits of the script context cannot be part of
+    // any valid compilation, as fragments containing them are only larger
     if let Some(op_count) = elem.ms.ext.ops_count_sat {
-        if op_count > MAX_OPS_PER_SCRIPT {
+        if op_count > limits.max_ops {
+            exceeded.set(Exceeded {
+                max_ops: true,
+                ..exceeded.get()
+            });
             return false;
         }
     }
+    if elem.ms.ext.pk_cost > limits.max_script_size {
+        exceeded.set(Exceeded {
+            max_script_size: true,
+            ..exceeded.get()
+        });
+        return false;
+    }
 
-    let elem_cost = elem.cost_1d(sat_prob, dissat_prob);
+    let elem_cost = elem.cost_1d(sat_prob, dissat_prob, params);
 
     let elem_key = CompilationKey::from_type(elem.ms.ty, elem.ms.ext.has_verify_form, dissat_prob);
 
@@ -650,7 +1028,7 @@ fn insert_elem<Pk: MiniscriptKey>(
     let is_worse = map
         .iter()
         .map(|(existing_key, existing_elem)| {
-            let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob);
+            let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob, params);
             existing_key.is_subtype(elem_key) && existing_elem_cost <= elem_cost
         })
         .fold(false, |acc, x| acc || x);
@@ -658,7 +1036,7 @@ fn insert_elem<Pk: MiniscriptKey>(
         // If the element is not worse any element in the map, remove elements
         // whose subtype is the current element and have worse cost.
         map.retain(|&existing_key, existing_elem| {
-            let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob);
+            let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob, params);
             !(elem_key.is_subtype(existing_key) && existing_elem_cost >= elem_cost)
         });
         map.insert(elem_key, elem);
@@ -679,9 +1057,20 @@ fn insert_elem_closure<Pk: MiniscriptKey>(
     astelem_ext: AstElemExt<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
+    limits: ScriptLimits,
+    exceeded: &Cell<Exceeded>,
+    params: &CompilerParams,
 ) {
     let mut cast_stack: VecDeque<AstElemExt<Pk>> = VecDeque::new();
-    if insert_elem(map, astelem_ext.clone(), sat_prob, dissat_prob) {
+    if insert_elem(
+        map,
+        astelem_ext.clone(),
+        sat_prob,
+        dissat_prob,
+        limits,
+        exceeded,
+        params,
+    ) {
         cast_stack.push_back(astelem_ext);
     }
 
@@ -691,7 +1080,15 @@ fn insert_elem_closure<Pk: MiniscriptKey>(
 
         for i in 0..casts.len() {
             if let Ok(new_ext) = casts[i].cast(&current) {
-                if insert_elem(map, new_ext.clone(), sat_prob, dissat_prob) {
+                if insert_elem(
+                    map,
+                    new_ext.clone(),
+                    sat_prob,
+                    dissat_prob,
+                    limits,
+                    exceeded,
+                    params,
+                ) {
                     cast_stack.push_back(new_ext);
                 }
             }
@@ -699,35 +1096,48 @@ fn insert_elem_closure<Pk: MiniscriptKey>(
     }
 }
 
-/// Insert the best wrapped compilations of a particular Terminal. If the
-/// dissat probability is None, then we directly get the closure of the element
-/// Otherwise, some wrappers require the compilation of the policy with dissat
-/// `None` because they convert it into a dissat around it.
+/// Insert the cast-closure of the compilation of a particular Terminal
+fn insert_best_wrapped<Pk: MiniscriptKey>(
+    policy_cache: &PolicyCache<Pk>,
+    map: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
+    data: AstElemExt<Pk>,
+    sat_prob: f64,
+    dissat_prob: Option<f64>,
+) {
+    insert_elem_closure(
+        map,
+        data,
+        sat_prob,
+        dissat_prob,
+        policy_cache.limits,
+        &policy_cache.exceeded,
+        &policy_cache.params,
+    );
+}
+
+/// If the dissat probability is not None, some wrappers require the
+/// compilation of the policy with dissat `None` because they convert it into
+/// a dissat around it.
 /// For example, `l` wrapper should it argument it dissat. `None` because it can
 /// always dissatisfy the policy outside and it find the better inner compilation
 /// given that it may be not be necessary to dissatisfy. For these elements, we
 /// apply the wrappers around the element once and bring them into the same
 /// dissat probability map and get their closure.
-fn insert_best_wrapped<Pk: MiniscriptKey>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+fn insert_q_zero_wrapped<Pk: MiniscriptKey>(
+    policy_cache: &mut PolicyCache<Pk>,
+    node: &PolicyNode<Pk>,
     map: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
-    data: AstElemExt<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<(), CompilerError> {
-    insert_elem_closure(map, data, sat_prob, dissat_prob);
-
     if dissat_prob.is_some() {
         let casts: [Cast<Pk>; 10] = all_casts::<Pk>();
+        let q_zero = best_compilations(policy_cache, node, sat_prob, None)?;
 
         for i in 0..casts.len() {
-            for x in best_compilations(policy_cache, policy, sat_prob, None)?.values() {
+            for x in q_zero.values() {
                 if let Ok(new_ext) = casts[i].cast(x) {
-                    insert_elem_closure(map, new_ext, sat_prob, dissat_prob);
+                    insert_best_wrapped(policy_cache, map, new_ext, sat_prob, dissat_prob);
                 }
             }
         }
@@ -738,11 +1148,8 @@ fn insert_best_wrapped<Pk: MiniscriptKey>(
 /// Get the best compilations of a policy with a given sat and dissat
 /// probabilities. This functions caches the results into a global policy cache.
 fn best_compilations<Pk>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+    policy_cache: &mut PolicyCache<Pk>,
+    node: &PolicyNode<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<HashMap<CompilationKey, AstElemExt<Pk>>, CompilerError>
@@ -752,23 +1159,50 @@ where
     //Check the cache for hits
     let ord_sat_prob = OrdF64(sat_prob);
     let ord_dissat_prob = dissat_prob.and_then(|x| Some(OrdF64(x)));
-    if let Some(ret) = policy_cache.get(&(policy.clone(), ord_sat_prob, ord_dissat_prob)) {
+    if let Some(ret) = policy_cache
+        .map
+        .get(&(node.id, ord_sat_prob, ord_dissat_prob))
+    {
         return Ok(ret.clone());
     }
+    if let Some(ret) = policy_cache.closest_compilations(node.id, sat_prob, dissat_prob) {
+        return Ok(ret);
+    }
 
+    // Only the compilations of this policy itself tell why it has none, so
+    // track them separately from those of the policy it is a part of
+    let outer = policy_cache.exceeded.replace(Exceeded::default());
+    let ret = compile_uncached(policy_cache, node, sat_prob, dissat_prob);
+    policy_cache.exceeded.set(outer);
+    ret
+}
+
+/// Helper function for `best_compilations` to compile a policy which is not
+/// cached for the given sat and dissat probabilities
+fn compile_uncached<Pk>(
+    policy_cache: &mut PolicyCache<Pk>,
+    node: &PolicyNode<Pk>,
+    sat_prob: f64,
+    dissat_prob: Option<f64>,
+) -> Result<HashMap<CompilationKey, AstElemExt<Pk>>, CompilerError>
+where
+    Pk: MiniscriptKey,
+{
+    let ord_sat_prob = OrdF64(sat_prob);
+    let ord_dissat_prob = dissat_prob.map(OrdF64);
+    let params = policy_cache.params;
     let mut ret = HashMap::new();
 
     //handy macro for good looking code
     macro_rules! insert_wrap {
         ($x:expr) => {
-            insert_best_wrapped(policy_cache, policy, &mut ret, $x, sat_prob, dissat_prob)?
+            insert_best_wrapped(policy_cache, &mut ret, $x, sat_prob, dissat_prob)
         };
     }
     macro_rules! compile_binary {
         ($l:expr, $r:expr, $w: expr, $f: expr) => {
             compile_binary(
                 policy_cache,
-                policy,
                 &mut ret,
                 $l,
                 $r,
@@ -776,14 +1210,13 @@ where
                 sat_prob,
                 dissat_prob,
                 $f,
-            )?
+            )
         };
     }
     macro_rules! compile_tern {
         ($a:expr, $b:expr, $c: expr, $w: expr) => {
             compile_tern(
                 policy_cache,
-                policy,
                 &mut ret,
                 $a,
                 $b,
@@ -791,25 +1224,35 @@ where
                 $w,
                 sat_prob,
                 dissat_prob,
-            )?
+            )
         };
     }
 
-    match *policy {
+    match *node.policy {
         Concrete::Key(ref pk) => {
-            insert_wrap!(AstElemExt::terminal(Terminal::PkH(
-                pk.to_pubkeyhash().clone()
-            )));
-            insert_wrap!(AstElemExt::terminal(Terminal::Pk(pk.clone())));
+            insert_wrap!(AstElemExt::terminal(
+                Terminal::PkH(pk.to_pubkeyhash().clone()),
+                &params
+            ));
+            insert_wrap!(AstElemExt::terminal(Terminal::Pk(pk.clone()), &params));
+        }
+        Concrete::After(n) => insert_wrap!(AstElemExt::terminal(Terminal::After(n), &params)),
+        Concrete::Older(n) => insert_wrap!(AstElemExt::terminal(Terminal::Older(n), &params)),
+        Concrete::Sha256(hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Sha256(hash), &params))
+        }
+        Concrete::Hash256(hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Hash256(hash), &params))
+        }
+        Concrete::Ripemd160(hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Ripemd160(hash), &params))
+        }
+        Concrete::Hash160(hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Hash160(hash), &params))
         }
-        Concrete::After(n) => insert_wrap!(AstElemExt::terminal(Terminal::After(n))),
-        Concrete::Older(n) => insert_wrap!(AstElemExt::terminal(Terminal::Older(n))),
-        Concrete::Sha256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Sha256(hash))),
-        Concrete::Hash256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash256(hash))),
-        Concrete::Ripemd160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Ripemd160(hash))),
-        Concrete::Hash160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash160(hash))),
         Concrete::And(ref subs) => {
             assert_eq!(subs.len(), 2, "and takes 2 args");
+            let subs = &node.subs;
             let mut left = best_compilations(policy_cache, &subs[0], sat_prob, dissat_prob)?;
             let mut right = best_compilations(policy_cache, &subs[1], sat_prob, dissat_prob)?;
             let mut q_zero_right = best_compilations(policy_cache, &subs[1], sat_prob, None)?;
@@ -826,7 +1269,7 @@ where
                     ExtData::from_false().has_verify_form,
                     dissat_prob,
                 ),
-                AstElemExt::terminal(Terminal::False),
+                AstElemExt::terminal(Terminal::False, &params),
             );
             compile_tern!(&mut left, &mut q_zero_right, &mut zero_comp, [1.0, 0.0]);
             compile_tern!(&mut right, &mut q_zero_left, &mut zero_comp, [1.0, 0.0]);
@@ -837,7 +1280,8 @@ where
             let rw = subs[1].0 as f64 / total;
 
             //and-or
-            if let (&Concrete::And(ref x), _) = (&subs[0].1, &subs[1].1) {
+            if let Concrete::And(_) = *node.subs[0].policy {
+                let x = &node.subs[0].subs;
                 let mut a1 = best_compilations(
                     policy_cache,
                     &x[0],
@@ -855,12 +1299,13 @@ where
                 let mut b2 = best_compilations(policy_cache, &x[1], lw * sat_prob, None)?;
 
                 let mut c =
-                    best_compilations(policy_cache, &subs[1].1, rw * sat_prob, dissat_prob)?;
+                    best_compilations(policy_cache, &node.subs[1], rw * sat_prob, dissat_prob)?;
 
                 compile_tern!(&mut a1, &mut b2, &mut c, [lw, rw]);
                 compile_tern!(&mut b1, &mut a2, &mut c, [lw, rw]);
             };
-            if let (_, &Concrete::And(ref x)) = (&subs[0].1, &subs[1].1) {
+            if let Concrete::And(_) = *node.subs[1].policy {
+                let x = &node.subs[1].subs;
                 let mut a1 = best_compilations(
                     policy_cache,
                     &x[0],
@@ -878,7 +1323,7 @@ where
                 let mut b2 = best_compilations(policy_cache, &x[1], rw * sat_prob, None)?;
 
                 let mut c =
-                    best_compilations(policy_cache, &subs[0].1, lw * sat_prob, dissat_prob)?;
+                    best_compilations(policy_cache, &node.subs[0], lw * sat_prob, dissat_prob)?;
 
                 compile_tern!(&mut a1, &mut b2, &mut c, [rw, lw]);
                 compile_tern!(&mut b1, &mut a2, &mut c, [rw, lw]);
@@ -897,12 +1342,14 @@ where
             let mut r_comp = vec![];
 
             for dissat_prob in dissat_probs(rw).iter() {
-                let l = best_compilations(policy_cache, &subs[0].1, lw * sat_prob, *dissat_prob)?;
+                let l =
+                    best_compilations(policy_cache, &node.subs[0], lw * sat_prob, *dissat_prob)?;
                 l_comp.push(l);
             }
 
             for dissat_prob in dissat_probs(lw).iter() {
-                let r = best_compilations(policy_cache, &subs[1].1, rw * sat_prob, *dissat_prob)?;
+                let r =
+                    best_compilations(policy_cache, &node.subs[1], rw * sat_prob, *dissat_prob)?;
                 r_comp.push(r);
             }
             compile_binary!(&mut l_comp[0], &mut r_comp[0], [lw, rw], Terminal::OrB);
@@ -931,14 +1378,14 @@ where
             let mut best_ws = Vec::with_capacity(n);
 
             let mut min_value = (0 as usize, f64::INFINITY as f64);
-            for (i, ast) in subs.iter().enumerate() {
+            for (i, ast) in node.subs.iter().enumerate() {
                 let sp = sat_prob * k_over_n;
                 //Expressions must be dissatisfiable
                 let dp = Some(dissat_prob.unwrap_or(0 as f64) + (1.0 - k_over_n) * sat_prob);
                 let be = best_e(policy_cache, ast, sp, dp)?;
                 let bw = best_w(policy_cache, ast, sp, dp)?;
 
-                let diff = be.cost_1d(sp, dp) - bw.cost_1d(sp, dp);
+                let diff = be.cost_1d(sp, dp, &params) - bw.cost_1d(sp, dp, &params);
                 best_es.push((be.comp_ext_data, be));
                 best_ws.push((bw.comp_ext_data, bw));
 
@@ -977,24 +1424,62 @@ where
                     }
                 })
                 .collect();
-            if key_vec.len() == subs.len() && subs.len() <= 20 {
-                insert_wrap!(AstElemExt::terminal(Terminal::ThreshM(k, key_vec)));
+            if key_vec.len() == subs.len() && subs.len() <= MAX_PUBKEYS_PER_MULTISIG {
+                insert_wrap!(AstElemExt::terminal(Terminal::ThreshM(k, key_vec), &params));
+            }
+
+            // Beyond the `multi` limit a `thresh` costs three opcodes per
+            // sub, so also try splitting an n-of-n threshold into nested
+            // `and`s, which may need as few as one. (Any `or` costs at least
+            // as many opcodes as the `thresh`.)
+            if n > MAX_PUBKEYS_PER_MULTISIG && k == n {
+                let split = Concrete::And(subs.clone()).binarized();
+                let split = policy_cache.intern(&split);
+                if let Ok(split_comp) =
+                    best_compilations(policy_cache, &split, sat_prob, dissat_prob)
+                {
+                    for elem in split_comp.values() {
+                        insert_wrap!(elem.clone());
+                    }
+                }
+            }
+        }
+        Concrete::WeightedThreshold(..) => {
+            // Compile the equivalent threshold with repeated subpolicies
+            let expanded = node.policy.binarized();
+            let expanded = policy_cache.intern(&expanded);
+            for elem in best_compilations(policy_cache, &expanded, sat_prob, dissat_prob)?.values()
+            {
+                insert_wrap!(elem.clone());
             }
         }
     }
+    insert_q_zero_wrapped(policy_cache, node, &mut ret, sat_prob, dissat_prob)?;
     for k in ret.keys() {
         debug_assert_eq!(k.dissat_prob, ord_dissat_prob);
     }
     if ret.len() == 0 {
-        // The only reason we are discarding elements out of compiler is because
-        // compilations exceed opcount or are non-malleable . If there no possible
-        // compilations for any policies regardless of dissat probability then it
-        // must have all compilations exceeded the Max Opcount because we already
-        // checked that policy must have non-malleable compilations before calling
-        // this compile function
-        Err(CompilerError::MaxOpCountExceeded)
+        // The only reasons we are discarding elements out of compiler are
+        // that compilations exceed the limits or are malleable. We already
+        // checked that the policy must have non-malleable compilations before
+        // calling this compile function, so unless a limit was exceeded
+        // there is no non-malleable compilation after all
+        let exceeded = policy_cache.exceeded.get();
+        if !exceeded.max_ops && !exceeded.max_script_size {
+            return Err(CompilerError::ImpossibleNonMalleableCompilation);
+        }
+        if let Concrete::Threshold(k, ref subs) = *node.policy {
+            if subs.len() > MAX_PUBKEYS_PER_MULTISIG {
+                return Err(CompilerError::ThresholdTooLarge(k, subs.len()));
+            }
+        }
+        if exceeded.max_script_size && !exceeded.max_ops {
+            Err(CompilerError::ScriptSizeExceeded)
+        } else {
+            Err(CompilerError::MaxOpCountExceeded)
+        }
     } else {
-        policy_cache.insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone());
+        policy_cache.insert(node.id, ord_sat_prob, ord_dissat_prob, ret.clone());
         Ok(ret)
     }
 }
@@ -1003,11 +1488,7 @@ where
 /// `sat_prob` and `dissat_prob` represent the sat and dissat probabilities of
 /// root or. `weights` represent the odds for taking each sub branch
 fn compile_binary<Pk, F>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+    policy_cache: &mut PolicyCache<Pk>,
     ret: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
     left_comp: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
     right_comp: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
@@ -1015,8 +1496,7 @@ fn compile_binary<Pk, F>(
     sat_prob: f64,
     dissat_prob: Option<f64>,
     bin_func: F,
-) -> Result<(), CompilerError>
-where
+) where
     Pk: MiniscriptKey,
     F: Fn(Arc<Miniscript<Pk>>, Arc<Miniscript<Pk>>) -> Terminal<Pk>,
 {
@@ -1028,22 +1508,17 @@ where
             l.comp_ext_data.branch_prob = Some(weights[0]);
             r.comp_ext_data.branch_prob = Some(weights[1]);
             if let Ok(new_ext) = AstElemExt::binary(ast, l, r) {
-                insert_best_wrapped(policy_cache, policy, ret, new_ext, sat_prob, dissat_prob)?;
+                insert_best_wrapped(policy_cache, ret, new_ext, sat_prob, dissat_prob);
             }
         }
     }
-    Ok(())
 }
 
 /// Helper function to compile different order of and_or fragments.
 /// `sat_prob` and `dissat_prob` represent the sat and dissat probabilities of
 /// root and_or node. `weights` represent the odds for taking each sub branch
 fn compile_tern<Pk: MiniscriptKey>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+    policy_cache: &mut PolicyCache<Pk>,
     ret: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
     a_comp: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
     b_comp: &mut HashMap<CompilationKey, AstElemExt<Pk>>,
@@ -1051,7 +1526,7 @@ fn compile_tern<Pk: MiniscriptKey>(
     weights: [f64; 2],
     sat_prob: f64,
     dissat_prob: Option<f64>,
-) -> Result<(), CompilerError> {
+) {
     for a in a_comp.values_mut() {
         let aref = Arc::clone(&a.ms);
         for b in b_comp.values_mut() {
@@ -1063,20 +1538,47 @@ fn compile_tern<Pk: MiniscriptKey>(
                 b.comp_ext_data.branch_prob = Some(weights[0]);
                 c.comp_ext_data.branch_prob = Some(weights[1]);
                 if let Ok(new_ext) = AstElemExt::ternary(ast, a, b, c) {
-                    insert_best_wrapped(policy_cache, policy, ret, new_ext, sat_prob, dissat_prob)?;
+                    insert_best_wrapped(policy_cache, ret, new_ext, sat_prob, dissat_prob);
                 }
             }
         }
     }
-    Ok(())
 }
 
-/// Obtain the best compilation of for p=1.0 and q=0
+/// Obtain the best compilation of for p=1.0 and q=0, with costs computed
+/// according to `params`.
+///
+/// Subpolicies are compiled for at most `MAX_CONTEXTS` sat and dissat
+/// probabilities each, reusing the compilations for the closest ones
+/// beyond that. For deeply nested policies the result may therefore not
+/// be the cheapest compilation.
 pub fn best_compilation<Pk: MiniscriptKey>(
     policy: &Concrete<Pk>,
+    params: &CompilerParams,
+) -> Result<Miniscript<Pk>, CompilerError> {
+    best_compilation_with_limits(policy, ScriptLimits::default(), params)
+}
+
+/// Obtain the best compilation of for p=1.0 and q=0 which respects the
+/// limits of the given output type, with costs computed according to
+/// `params`. Like `best_compilation`, the result may not be the cheapest
+/// for deeply nested policies.
+pub fn best_compilation_for<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    desc_type: DescriptorType,
+    params: &CompilerParams,
 ) -> Result<Miniscript<Pk>, CompilerError> {
-    let mut policy_cache = HashMap::new();
-    let x = &*best_t(&mut policy_cache, policy, 1.0, None)?.ms;
+    best_compilation_with_limits(policy, desc_type.limits(), params)
+}
+
+fn best_compilation_with_limits<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    limits: ScriptLimits,
+    params: &CompilerParams,
+) -> Result<Miniscript<Pk>, CompilerError> {
+    let mut policy_cache = PolicyCache::new(limits, *params);
+    let node = policy_cache.intern(policy);
+    let x = &*best_t(&mut policy_cache, &node, 1.0, None)?.ms;
     if !x.ty.mall.safe {
         Err(CompilerError::TopLevelNonSafe)
     } else if !x.ty.mall.non_malleable {
@@ -1086,44 +1588,362 @@ pub fn best_compilation<Pk: MiniscriptKey>(
     }
 }
 
+/// Maximum number of satisfaction paths listed in a `Candidate`
+const MAX_BRANCHES: usize = 1000;
+
+/// A compilation of a policy, along with the costs the compiler compared it
+/// on. Witness costs are in bytes, counted as by the compiler.
+///
+/// Candidates are not the globally cheapest Miniscripts: the compiler keeps
+/// only the cheapest compilation of each type for every subpolicy, so there
+/// are few candidates for a policy, mostly differing in their top-level
+/// fragment. Ones which only differ in their wrappers are dropped in favor
+/// of the cheapest.
+#[derive(Clone, Debug)]
+pub struct Candidate<Pk: MiniscriptKey> {
+    /// The compiled Miniscript
+    pub ms: Miniscript<Pk>,
+    /// The cost the compiler minimized, per `CompilerParams`
+    pub cost: f64,
+    /// Size of the script in bytes
+    pub script_size: usize,
+    /// Witness cost of a satisfaction, averaged over spending paths using
+    /// the probabilities of the policy
+    pub expected_witness_cost: f64,
+    /// Witness cost of the most expensive satisfaction
+    pub worst_witness_cost: f64,
+    /// The worst case static (unexecuted) ops count of the script
+    pub ops_count_static: usize,
+    /// The worst case ops count of a satisfaction, which must not exceed
+    /// `MAX_OPS_PER_SCRIPT`
+    pub ops_count_sat: Option<usize>,
+    /// Each spending path, as the keys, hashes and timelocks it needs, with
+    /// its witness cost. `None` if there are more than 1000 paths.
+    pub branch_costs: Option<Vec<(Vec<Semantic<Pk>>, f64)>>,
+}
+
+/// Obtain up to `n` of the best compilations for p=1.0 and q=0, cheapest
+/// first. See `Candidate` for which alternatives are considered; as with
+/// `best_compilation`, even the first may not be the cheapest compilation.
+pub fn best_compilations_n<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    params: &CompilerParams,
+    n: usize,
+) -> Result<Vec<Candidate<Pk>>, CompilerError> {
+    let mut policy_cache = PolicyCache::new(ScriptLimits::default(), *params);
+    let node = policy_cache.intern(policy);
+    let compilations = best_compilations(&mut policy_cache, &node, 1.0, None)?;
+    let mut candidates: Vec<&AstElemExt<Pk>> = compilations
+        .values()
+        .filter(|val| {
+            val.ms.ty.corr.base == types::Base::B
+                && val.ms.ty.mall.safe
+                && val.ms.ty.mall.non_malleable
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(CompilerError::TopLevelNonSafe);
+    }
+    candidates.sort_by_key(|ext| OrdF64(ext.cost_1d(1.0, None, params)));
+    let mut fragments: Vec<&Miniscript<Pk>> = vec![];
+    candidates.retain(|ext| {
+        if fragments.iter().any(|ms| same_fragments(ms, &ext.ms)) {
+            false
+        } else {
+            fragments.push(&ext.ms);
+            true
+        }
+    });
+    candidates.truncate(n);
+
+    Ok(candidates
+        .into_iter()
+        .map(|ext| Candidate {
+            cost: ext.cost_1d(1.0, None, params),
+            script_size: ext.ms.ext.pk_cost,
+            expected_witness_cost: ext.comp_ext_data.sat_cost,
+            worst_witness_cost: ext.comp_ext_data.max_sat_cost,
+            ops_count_static: ext.ms.ext.ops_count_static,
+            ops_count_sat: ext.ms.ext.ops_count_sat,
+            branch_costs: sat_paths(&ext.ms, params).map(|(paths, _)| paths),
+            ms: (*ext.ms).clone(),
+        })
+        .collect())
+}
+
+/// Helper function for `best_compilations_n` to check whether two
+/// compilations only differ in their wrappers
+fn same_fragments<Pk: MiniscriptKey>(a: &Miniscript<Pk>, b: &Miniscript<Pk>) -> bool {
+    let (a, b) = (unwrapped(a), unwrapped(b));
+    match (&a.node, &b.node) {
+        (&Terminal::AndV(ref al, ref ar), &Terminal::AndV(ref bl, ref br))
+        | (&Terminal::AndB(ref al, ref ar), &Terminal::AndB(ref bl, ref br))
+        | (&Terminal::OrB(ref al, ref ar), &Terminal::OrB(ref bl, ref br))
+        | (&Terminal::OrD(ref al, ref ar), &Terminal::OrD(ref bl, ref br))
+        | (&Terminal::OrC(ref al, ref ar), &Terminal::OrC(ref bl, ref br))
+        | (&Terminal::OrI(ref al, ref ar), &Terminal::OrI(ref bl, ref br)) => {
+            same_fragments(al, bl) && same_fragments(ar, br)
+        }
+        (&Terminal::AndOr(ref a1, ref a2, ref a3), &Terminal::AndOr(ref b1, ref b2, ref b3)) => {
+            same_fragments(a1, b1) && same_fragments(a2, b2) && same_fragments(a3, b3)
+        }
+        (&Terminal::Thresh(ak, ref asubs), &Terminal::Thresh(bk, ref bsubs)) => {
+            ak == bk
+                && asubs.len() == bsubs.len()
+                && asubs
+                    .iter()
+                    .zip(bsubs.iter())
+                    .all(|(a, b)| same_fragments(a, b))
+        }
+        (an, bn) => an == bn,
+    }
+}
+
+/// Helper function for `same_fragments` to strip the wrappers around the
+/// top-level fragment of a compilation
+fn unwrapped<Pk: MiniscriptKey>(ms: &Miniscript<Pk>) -> &Miniscript<Pk> {
+    match ms.node {
+        Terminal::Alt(ref sub)
+        | Terminal::Swap(ref sub)
+        | Terminal::Check(ref sub)
+        | Terminal::DupIf(ref sub)
+        | Terminal::Verify(ref sub)
+        | Terminal::NonZero(ref sub)
+        | Terminal::ZeroNotEqual(ref sub) => unwrapped(sub),
+        Terminal::AndV(ref sub, ref t) if t.node == Terminal::True => unwrapped(sub),
+        Terminal::OrI(ref f, ref sub) if f.node == Terminal::False => unwrapped(sub),
+        Terminal::OrI(ref sub, ref f) if f.node == Terminal::False => unwrapped(sub),
+        _ => ms,
+    }
+}
+
+/// Satisfaction paths of a fragment, each with the conditions it needs and
+/// its witness cost
+type Paths<Pk> = Vec<(Vec<Semantic<Pk>>, f64)>;
+
+/// Computes the satisfaction paths of a fragment, along with its
+/// `CompilerExtData`, or `None` if there are more than `MAX_BRANCHES`
+/// paths. The cost of a path is the satisfaction cost `CompilerExtData`
+/// gives when the branches on the path are taken with probability 1 and
+/// the others with probability 0.
+fn sat_paths<Pk: MiniscriptKey>(
+    ms: &Miniscript<Pk>,
+    params: &CompilerParams,
+) -> Option<(Paths<Pk>, CompilerExtData)> {
+    let children: Vec<&Miniscript<Pk>> = match ms.node {
+        Terminal::Alt(ref sub)
+        | Terminal::Swap(ref sub)
+        | Terminal::Check(ref sub)
+        | Terminal::DupIf(ref sub)
+        | Terminal::Verify(ref sub)
+        | Terminal::NonZero(ref sub)
+        | Terminal::ZeroNotEqual(ref sub) => vec![sub],
+        Terminal::AndV(ref l, ref r)
+        | Terminal::AndB(ref l, ref r)
+        | Terminal::OrB(ref l, ref r)
+        | Terminal::OrD(ref l, ref r)
+        | Terminal::OrC(ref l, ref r)
+        | Terminal::OrI(ref l, ref r) => vec![l, r],
+        Terminal::AndOr(ref a, ref b, ref c) => vec![a, b, c],
+        Terminal::Thresh(_, ref subs) => subs.iter().map(|sub| &**sub).collect(),
+        _ => vec![],
+    };
+    let subs = children
+        .into_iter()
+        .map(|sub| sat_paths(sub, params))
+        .collect::<Option<Vec<_>>>()?;
+    let data = compiler_ext_data(&ms.node, params, |i| {
+        Some(CompilerExtData {
+            branch_prob: Some(0.5),
+            ..subs[i].1
+        })
+    })?;
+
+    // The cost of a path through the children, given the cost of the path
+    // through each child whose branch is taken
+    let path_cost = |costs: &[Option<f64>]| {
+        compiler_ext_data(&ms.node, params, |i| {
+            let (prob, cost) = match costs[i] {
+                Some(cost) => (1.0, cost),
+                None => (0.0, 0.0),
+            };
+            Some(CompilerExtData {
+                branch_prob: Some(prob),
+                sat_cost: cost,
+                max_sat_cost: cost,
+                ..subs[i].1
+            })
+        })
+        .map_or(f64::INFINITY, |data| data.sat_cost)
+    };
+    // The paths through the children which only take the branch of child `i`
+    let single_paths = |i: usize| {
+        subs[i]
+            .0
+            .iter()
+            .map(|&(ref conds, cost)| {
+                let mut costs = vec![None; subs.len()];
+                costs[i] = Some(cost);
+                (conds.clone(), path_cost(&costs))
+            })
+            .collect::<Paths<Pk>>()
+    };
+    let leaf = |cond: Semantic<Pk>| vec![(vec![cond], data.sat_cost)];
+
+    let paths = match ms.node {
+        Terminal::Pk(ref pk) => leaf(Semantic::KeyHash(pk.to_pubkeyhash())),
+        Terminal::PkH(ref pkh) => leaf(Semantic::KeyHash(pkh.clone())),
+        Terminal::After(t) => leaf(Semantic::After(t)),
+        Terminal::Older(t) => leaf(Semantic::Older(t)),
+        Terminal::Sha256(h) => leaf(Semantic::Sha256(h)),
+        Terminal::Hash256(h) => leaf(Semantic::Hash256(h)),
+        Terminal::Ripemd160(h) => leaf(Semantic::Ripemd160(h)),
+        Terminal::Hash160(h) => leaf(Semantic::Hash160(h)),
+        Terminal::True => vec![(vec![], data.sat_cost)],
+        Terminal::False => vec![],
+        Terminal::Alt(..)
+        | Terminal::Swap(..)
+        | Terminal::Check(..)
+        | Terminal::DupIf(..)
+        | Terminal::Verify(..)
+        | Terminal::NonZero(..)
+        | Terminal::ZeroNotEqual(..) => single_paths(0),
+        Terminal::AndV(..) | Terminal::AndB(..) => and_paths(&subs[0].0, &subs[1].0, |l, r| {
+            path_cost(&[Some(l), Some(r)])
+        })?,
+        Terminal::AndOr(..) => {
+            let mut ret = and_paths(&subs[0].0, &subs[1].0, |a, b| {
+                path_cost(&[Some(a), Some(b), None])
+            })?;
+            ret.append(&mut single_paths(2));
+            ret
+        }
+        Terminal::OrB(..) | Terminal::OrD(..) | Terminal::OrC(..) | Terminal::OrI(..) => {
+            let mut ret = single_paths(0);
+            ret.append(&mut single_paths(1));
+            ret
+        }
+        Terminal::Thresh(k, _) => {
+            // `CompilerExtData` averages over the subsets of satisfied
+            // fragments, so add up the costs of each subset instead
+            let subs = subs
+                .iter()
+                .map(|&(ref paths, data)| Some((paths.clone(), data.dissat_cost?)))
+                .collect::<Option<Vec<_>>>()?;
+            thresh_paths(k, &subs)?
+        }
+        Terminal::ThreshM(k, ref keys) => {
+            // Every choice of `k` keys costs the same
+            let subs: Vec<_> = keys
+                .iter()
+                .map(|pk| {
+                    (
+                        vec![(vec![Semantic::KeyHash(pk.to_pubkeyhash())], 0.0)],
+                        0.0,
+                    )
+                })
+                .collect();
+            thresh_paths(k, &subs)?
+                .into_iter()
+                .map(|(conds, _)| (conds, data.sat_cost))
+                .collect()
+        }
+    };
+    if paths.len() > MAX_BRANCHES {
+        None
+    } else {
+        Some((paths, data))
+    }
+}
+
+/// Adds a cost to every path
+fn add_cost<Pk: MiniscriptKey>(paths: Paths<Pk>, cost: f64) -> Paths<Pk> {
+    paths
+        .into_iter()
+        .map(|(conds, c)| (conds, c + cost))
+        .collect()
+}
+
+/// Computes the paths satisfying both of two fragments, whose cost is
+/// given by `cost` from the costs of the paths through each, or `None` if
+/// there are more than `MAX_BRANCHES`
+fn and_paths<Pk, F>(left: &Paths<Pk>, right: &Paths<Pk>, cost: F) -> Option<Paths<Pk>>
+where
+    Pk: MiniscriptKey,
+    F: Fn(f64, f64) -> f64,
+{
+    if left.len() * right.len() > MAX_BRANCHES {
+        return None;
+    }
+    let mut ret = Vec::with_capacity(left.len() * right.len());
+    for &(ref lconds, lcost) in left {
+        for &(ref rconds, rcost) in right {
+            let mut conds = lconds.clone();
+            conds.extend(rconds.iter().cloned());
+            conds.sort();
+            conds.dedup();
+            ret.push((conds, cost(lcost, rcost)));
+        }
+    }
+    Some(ret)
+}
+
+/// Computes the paths satisfying exactly `k` of the given fragments and
+/// dissatisfying the others, or `None` if there are more than `MAX_BRANCHES`
+fn thresh_paths<Pk: MiniscriptKey>(k: usize, subs: &[(Paths<Pk>, f64)]) -> Option<Paths<Pk>> {
+    // `table[j]` holds the paths satisfying `j` of the fragments
+    // considered so far
+    let mut table: Vec<Paths<Pk>> = vec![vec![]; k + 1];
+    table[0] = vec![(vec![], 0.0)];
+    for &(ref sat, dissat) in subs {
+        for j in (0..k + 1).rev() {
+            let mut new = add_cost(table[j].clone(), dissat);
+            if j > 0 {
+                new.append(&mut and_paths(&table[j - 1], sat, |l, r| l + r)?);
+            }
+            if new.len() > MAX_BRANCHES {
+                return None;
+            }
+            table[j] = new;
+        }
+    }
+    table.pop()
+}
+
 /// Obtain the best B expression with given sat and dissat
 fn best_t<Pk>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+    policy_cache: &mut PolicyCache<Pk>,
+    node: &PolicyNode<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<AstElemExt<Pk>, CompilerError>
 where
     Pk: MiniscriptKey,
 {
-    best_compilations(policy_cache, policy, sat_prob, dissat_prob)?
+    let params = policy_cache.params;
+    best_compilations(policy_cache, node, sat_prob, dissat_prob)?
         .into_iter()
         .filter(|&(key, _)| {
             key.ty.corr.base == types::Base::B
                 && key.dissat_prob == dissat_prob.and_then(|x| Some(OrdF64(x)))
         })
         .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob, &params)))
         .ok_or(CompilerError::MaxOpCountExceeded)
 }
 
 /// Obtain the B.deu expression with the given sat and dissat
 fn best_e<Pk>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+    policy_cache: &mut PolicyCache<Pk>,
+    node: &PolicyNode<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<AstElemExt<Pk>, CompilerError>
 where
     Pk: MiniscriptKey,
 {
-    best_compilations(policy_cache, policy, sat_prob, dissat_prob)?
+    let params = policy_cache.params;
+    best_compilations(policy_cache, node, sat_prob, dissat_prob)?
         .into_iter()
         .filter(|&(ref key, ref val)| {
             key.ty.corr.base == types::Base::B
@@ -1132,24 +1952,22 @@ where
                 && key.dissat_prob == dissat_prob.and_then(|x| Some(OrdF64(x)))
         })
         .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob, &params)))
         .ok_or(CompilerError::MaxOpCountExceeded)
 }
 
 /// Obtain the W.deu expression with the given sat and dissat
 fn best_w<Pk>(
-    policy_cache: &mut HashMap<
-        (Concrete<Pk>, OrdF64, Option<OrdF64>),
-        HashMap<CompilationKey, AstElemExt<Pk>>,
-    >,
-    policy: &Concrete<Pk>,
+    policy_cache: &mut PolicyCache<Pk>,
+    node: &PolicyNode<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<AstElemExt<Pk>, CompilerError>
 where
     Pk: MiniscriptKey,
 {
-    best_compilations(policy_cache, policy, sat_prob, dissat_prob)?
+    let params = policy_cache.params;
+    best_compilations(policy_cache, node, sat_prob, dissat_prob)?
         .into_iter()
         .filter(|&(ref key, ref val)| {
             key.ty.corr.base == types::Base::W
@@ -1158,7 +1976,7 @@ where
                 && key.dissat_prob == dissat_prob.and_then(|x| Some(OrdF64(x)))
         })
         .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob, &params)))
         .ok_or(CompilerError::MaxOpCountExceeded)
 }
 
@@ -1173,6 +1991,7 @@ mod tests {
     use policy::Liftable;
     use BitcoinSig;
     use DummyKey;
+    use RelLockTime;
 
     type SPolicy = Concrete<String>;
     type DummyPolicy = Concrete<DummyKey>;
@@ -1246,17 +2065,27 @@ mod tests {
     #[test]
     fn compile_q() {
         let policy = SPolicy::from_str("or(1@and(pk(),pk()),127@pk())").expect("parsing");
-        let compilation = best_t(&mut HashMap::new(), &policy, 1.0, None).unwrap();
+        let mut policy_cache = PolicyCache::new(ScriptLimits::default(), CompilerParams::default());
+        let node = policy_cache.intern(&policy);
+        let compilation = best_t(&mut policy_cache, &node, 1.0, None).unwrap();
 
-        assert_eq!(compilation.cost_1d(1.0, None), 88.0 + 74.109375);
+        assert_eq!(
+            compilation.cost_1d(1.0, None, &CompilerParams::default()),
+            88.0 + 74.109375
+        );
         assert_eq!(policy.lift().sorted(), compilation.ms.lift().sorted());
 
         let policy = SPolicy::from_str(
                 "and(and(and(or(127@thresh(2,pk(),pk(),thresh(2,or(127@pk(),1@pk()),after(100),or(and(pk(),after(200)),and(pk(),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925))),pk())),1@pk()),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925)),or(127@pk(),1@after(300))),or(127@after(400),pk()))"
             ).expect("parsing");
-        let compilation = best_t(&mut HashMap::new(), &policy, 1.0, None).unwrap();
+        let mut policy_cache = PolicyCache::new(ScriptLimits::default(), CompilerParams::default());
+        let node = policy_cache.intern(&policy);
+        let compilation = best_t(&mut policy_cache, &node, 1.0, None).unwrap();
 
-        assert_eq!(compilation.cost_1d(1.0, None), 437.0 + 299.4003295898438);
+        assert_eq!(
+            compilation.cost_1d(1.0, None, &CompilerParams::default()),
+            437.0 + 299.4003295898438
+        );
         assert_eq!(policy.lift().sorted(), compilation.ms.lift().sorted());
     }
 
@@ -1303,7 +2132,7 @@ mod tests {
             (
                 1,
                 Concrete::And(vec![
-                    Concrete::Older(10000),
+                    Concrete::Older(RelLockTime::from_consensus(10000).unwrap()),
                     Concrete::Threshold(2, key_pol[5..8].to_owned()),
                 ]),
             ),
@@ -1391,48 +2220,317 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn compile_candidates() {
+        let params = CompilerParams::default();
+        let policy = SPolicy::from_str("or(9@pk(A),1@and(pk(B),older(144)))").unwrap();
+        let candidates = policy.compile_candidates(&params, 5).unwrap();
+
+        assert!(candidates.len() > 1 && candidates.len() <= 5);
+        assert_eq!(candidates[0].ms, policy.compile().unwrap());
+        for pair in candidates.windows(2) {
+            assert!(pair[0].cost <= pair[1].cost);
+        }
+        for cand in &candidates {
+            assert_eq!(cand.script_size, cand.ms.ext.pk_cost);
+            assert_eq!(
+                cand.cost,
+                cand.script_size as f64 + cand.expected_witness_cost
+            );
+            assert!(cand.ops_count_sat.unwrap() <= MAX_OPS_PER_SCRIPT);
+            assert_eq!(cand.ms.lift().sorted(), policy.lift().sorted());
+
+            // One branch for each key, the most expensive one giving the
+            // worst-case cost
+            let branches = cand.branch_costs.as_ref().unwrap();
+            assert_eq!(branches.len(), 2);
+            let worst = branches.iter().map(|b| b.1).fold(0.0, f64::max);
+            assert_eq!(worst, cand.worst_witness_cost);
+            assert!(cand.expected_witness_cost <= cand.worst_witness_cost);
+        }
+        let branches = candidates[0].branch_costs.as_ref().unwrap();
+        assert!(branches.contains(&(vec![Semantic::KeyHash("A".to_owned())], 73.0)));
+        // Weighting the branches by their probabilities gives the expected
+        // cost
+        let expected: f64 = branches
+            .iter()
+            .map(|&(ref conds, cost)| {
+                if conds.len() == 1 {
+                    0.9 * cost
+                } else {
+                    0.1 * cost
+                }
+            })
+            .sum();
+        assert!((expected - candidates[0].expected_witness_cost).abs() < 1e-9);
+        // Compilations which only differ in their wrappers are not repeated
+        for (i, cand) in candidates.iter().enumerate() {
+            for other in &candidates[i + 1..] {
+                assert!(!same_fragments(&cand.ms, &other.ms));
+            }
+        }
+        assert_eq!(
+            candidates.len(),
+            policy.compile_candidates(&params, 100).unwrap().len()
+        );
+        assert_eq!(policy.compile_candidates(&params, 1).unwrap().len(), 1);
+
+        let policy = SPolicy::from_str("thresh(2,pk(A),pk(B),pk(C))").unwrap();
+        for cand in policy.compile_candidates(&params, 10).unwrap() {
+            let branches = cand.branch_costs.unwrap();
+            assert_eq!(branches.len(), 3);
+            let worst = branches.iter().map(|b| b.1).fold(0.0, f64::max);
+            assert_eq!(worst, cand.worst_witness_cost);
+        }
+    }
+
+    #[test]
+    fn compile_large_thresh() {
+        let (keys, _) = pubkeys_and_a_sig(100);
+        let thresh = |k: usize, n: usize| -> BPolicy {
+            let subs: Vec<String> = keys[..n].iter().map(|k| format!("pk({})", k)).collect();
+            BPolicy::from_str(&format!("thresh({},{})", k, subs.join(","))).unwrap()
+        };
+
+        // Too many keys for `multi`, but `thresh` fits within the limits
+        let policy = thresh(15, 30);
+        let ms = policy.compile().unwrap();
+        assert_eq!(ms.lift().sorted(), policy.lift().sorted());
+        assert!(ms.ext.ops_count_sat.unwrap() <= MAX_OPS_PER_SCRIPT);
+        assert_eq!(
+            policy.compile_to_descriptor(DescriptorType::Sh),
+            Err(CompilerError::ThresholdTooLarge(15, 30))
+        );
+
+        // `thresh` would exceed the opcount, but the threshold can be
+        // split into `and`s
+        let policy = thresh(100, 100);
+        let ms = policy.compile().unwrap();
+        assert_eq!(ms.lift().sorted(), policy.lift().sorted());
+        assert!(ms.ext.ops_count_sat.unwrap() <= MAX_OPS_PER_SCRIPT);
+
+        // ...but other thresholds cannot
+        assert_eq!(
+            thresh(1, 100).compile(),
+            Err(CompilerError::ThresholdTooLarge(1, 100))
+        );
+
+        // A threshold inside a larger one is reported itself
+        let policy = BPolicy::from_str(&format!(
+            "thresh(2,{},{})",
+            keys[..20]
+                .iter()
+                .map(|k| format!("pk({})", k))
+                .collect::<Vec<_>>()
+                .join(","),
+            thresh(1, 100)
+        ))
+        .unwrap();
+        assert_eq!(
+            policy.compile(),
+            Err(CompilerError::ThresholdTooLarge(1, 100))
+        );
+
+        // A threshold without compilations for another reason than the
+        // limits is not reported as too large: here all are malleable
+        let hashes: Vec<String> = (0..21)
+            .map(|i| format!("sha256({}{:02x})", "0".repeat(62), i))
+            .collect();
+        let policy = SPolicy::from_str(&format!("thresh(1,{})", hashes.join(","))).unwrap();
+        let mut policy_cache = PolicyCache::new(ScriptLimits::default(), CompilerParams::default());
+        let node = policy_cache.intern(&policy);
+        assert_eq!(
+            best_compilations(&mut policy_cache, &node, 1.0, None).err(),
+            Some(CompilerError::ImpossibleNonMalleableCompilation)
+        );
+    }
+
+    #[test]
+    fn compile_deep() {
+        // Subpolicies deep down are compiled for exponentially many sat
+        // and dissat probabilities, unless they are capped
+        let mut policy = "pk(K32)".to_owned();
+        for i in (0..32).rev() {
+            let frag = if i % 2 == 0 { "or" } else { "and" };
+            policy = format!("{}(pk(K{}),{})", frag, i, policy);
+        }
+        let policy = SPolicy::from_str(&policy).unwrap();
+        let mut policy_cache = PolicyCache::new(ScriptLimits::default(), CompilerParams::default());
+        let node = policy_cache.intern(&policy);
+        let compilation = best_t(&mut policy_cache, &node, 1.0, None).unwrap();
+        assert_eq!(policy.lift().sorted(), compilation.ms.lift().sorted());
+        for contexts in policy_cache.contexts.values() {
+            assert!(contexts.len() <= MAX_CONTEXTS + 1);
+        }
+    }
+
+    #[test]
+    fn compile_deep_capped() {
+        // The cap may cost a more expensive compilation than compiling
+        // every subpolicy for all its sat and dissat probabilities
+        let mut policy = "pk(K10)".to_owned();
+        for i in (0..10).rev() {
+            policy = if i % 2 == 0 {
+                format!("or({}@pk(K{}),{})", i % 3 + 1, i, policy)
+            } else {
+                format!("and(pk(K{}),{})", i, policy)
+            };
+        }
+        let policy = SPolicy::from_str(&policy).unwrap();
+        let params = CompilerParams::default();
+        let mut costs = vec![];
+        for &max_contexts in &[MAX_CONTEXTS, !0] {
+            let mut policy_cache = PolicyCache::new(ScriptLimits::default(), params);
+            policy_cache.max_contexts = max_contexts;
+            let node = policy_cache.intern(&policy);
+            let compilation = best_t(&mut policy_cache, &node, 1.0, None).unwrap();
+            assert_eq!(policy.lift().sorted(), compilation.ms.lift().sorted());
+            let contexts = policy_cache.contexts.values().map(Vec::len).max();
+            costs.push((compilation.cost_1d(1.0, None, &params), contexts));
+        }
+        assert_eq!(costs[0].1, Some(MAX_CONTEXTS));
+        assert!(costs[1].1 > Some(MAX_CONTEXTS));
+        assert!(costs[1].0 <= costs[0].0);
+    }
+
+    #[test]
+    fn compile_params() {
+        let (keys, _) = pubkeys_and_a_sig(10);
+        let default = CompilerParams::default();
+
+        // Signature size only changes the satisfaction cost
+        let policy = SPolicy::from_str("pk(A)").unwrap();
+        let params = CompilerParams {
+            sig_size: 72,
+            ..default
+        };
+        let mut policy_cache = PolicyCache::new(ScriptLimits::default(), params);
+        let node = policy_cache.intern(&policy);
+        let compilation = best_t(&mut policy_cache, &node, 1.0, None).unwrap();
+        assert_eq!(compilation.cost_1d(1.0, None, &params), 35.0 + 72.0);
+        assert_eq!(compilation.cost_1d(1.0, None, &default), 35.0 + 72.0);
+
+        // Minimizing the worst case gives a smaller maximum satisfaction
+        let policy: BPolicy = policy_str!(
+            "or(127@pk({}),1@and(pk({}),or(1@pk({}),9@and(pk({}),pk({})))))",
+            keys[0],
+            keys[1],
+            keys[2],
+            keys[3],
+            keys[4]
+        );
+        let worst_case = CompilerParams {
+            cost_model: CostModel::WorstCase,
+            ..default
+        };
+        let mut policy_cache = PolicyCache::new(ScriptLimits::default(), worst_case);
+        let node = policy_cache.intern(&policy);
+        let compilation = best_t(&mut policy_cache, &node, 1.0, None).unwrap();
+        assert!(compilation.comp_ext_data.max_sat_cost >= compilation.comp_ext_data.sat_cost);
+        let ms_worst = best_compilation(&policy, &worst_case).unwrap();
+        let ms_expected = best_compilation(&policy, &default).unwrap();
+        assert!(ms_worst.max_satisfaction_size(1) < ms_expected.max_satisfaction_size(1));
+
+        // Expensive script bytes give smaller scripts, expensive witness
+        // bytes give smaller satisfactions
+        let ms_script = best_compilation(
+            &policy,
+            &CompilerParams {
+                script_weight: 100.0,
+                ..default
+            },
+        )
+        .unwrap();
+        let ms_witness = best_compilation(
+            &policy,
+            &CompilerParams {
+                witness_weight: 100.0,
+                ..default
+            },
+        )
+        .unwrap();
+        assert!(ms_script.script_size() < ms_expected.script_size());
+        assert!(ms_witness.script_size() > ms_expected.script_size());
+    }
 }
 
 #[cfg(all(test, feature = "unstable"))]
 mod benches {
-    use secp256k1;
     use std::str::FromStr;
     use test::{black_box, Bencher};
 
-    use Concrete;
-    use ParseTree;
+    use policy::Concrete;
 
     #[bench]
     pub fn compile(bh: &mut Bencher) {
-        let desc = Concrete::<secp256k1::PublicKey>::from_str(
-            "and(thresh(2,and(sha256(),or(sha256(),pk())),pk(),pk(),pk(),sha256()),pkh())",
+        let policy = Concrete::<String>::from_str(
+            "and(thresh(2,and(sha256(1111111111111111111111111111111111111111111111111111111111111111),or(sha256(2222222222222222222222222222222222222222222222222222222222222222),pk(A))),pk(B),pk(C),pk(D),sha256(3333333333333333333333333333333333333333333333333333333333333333)),pk(E))",
         )
         .expect("parsing");
         bh.iter(|| {
-            let pt = ParseTree::compile(&desc);
-            black_box(pt);
+            let ms = policy.compile();
+            black_box(ms);
         });
     }
 
     #[bench]
     pub fn compile_large(bh: &mut Bencher) {
-        let desc = Concrete::<secp256k1::PublicKey>::from_str(
-            "or(pkh(),thresh(9,sha256(),pkh(),pk(),and(or(pkh(),pk()),pk()),time_e(),pk(),pk(),pk(),pk(),and(pk(),pk())))"
-        ).expect("parsing");
+        let policy = Concrete::<String>::from_str(
+            "or(pk(A),thresh(9,sha256(1111111111111111111111111111111111111111111111111111111111111111),pk(B),pk(C),and(or(pk(D),pk(E)),pk(F)),after(100),pk(G),pk(H),pk(I),pk(J),and(pk(K),pk(L))))",
+        )
+        .expect("parsing");
         bh.iter(|| {
-            let pt = ParseTree::compile(&desc);
-            black_box(pt);
+            let ms = policy.compile();
+            black_box(ms);
         });
     }
 
     #[bench]
     pub fn compile_xlarge(bh: &mut Bencher) {
-        let desc = Concrete::<secp256k1::PublicKey>::from_str(
-            "or(pk(),thresh(4,pkh(),time_e(),multi(),and(after(),or(pkh(),or(pkh(),and(pkh(),thresh(2,multi(),or(pkh(),and(thresh(5,sha256(),or(pkh(),pkh()),pkh(),pkh(),pkh(),multi(),pkh(),multi(),pk(),pkh(),pk()),pkh())),pkh(),or(and(pkh(),pk()),pk()),after()))))),pkh()))"
-        ).expect("parsing");
+        let policy = Concrete::<String>::from_str(
+            "or(pk(A),thresh(4,pk(B),after(100),thresh(2,pk(C),pk(D),pk(E)),and(after(200),or(pk(F),or(pk(G),and(pk(H),thresh(2,thresh(2,pk(I),pk(J)),or(pk(K),and(thresh(5,sha256(1111111111111111111111111111111111111111111111111111111111111111),or(pk(L),pk(M)),pk(N),pk(O),pk(P),thresh(2,pk(Q),pk(R)),pk(S),thresh(2,pk(T),pk(U)),pk(V),pk(W),pk(X)),pk(Y))),pk(Z),or(and(pk(AA),pk(AB)),pk(AC)),after(300)))))),pk(AD)))",
+        )
+        .expect("parsing");
+        bh.iter(|| {
+            let ms = policy.compile();
+            black_box(ms);
+        });
+    }
+
+    #[bench]
+    pub fn compile_50_keys(bh: &mut Bencher) {
+        let subs: Vec<String> = (0..10)
+            .map(|i| {
+                format!(
+                    "or(and(pk(K{}),pk(K{})),thresh(2,pk(K{}),pk(K{}),pk(K{})))",
+                    5 * i,
+                    5 * i + 1,
+                    5 * i + 2,
+                    5 * i + 3,
+                    5 * i + 4
+                )
+            })
+            .collect();
+        let policy = Concrete::<String>::from_str(&format!("thresh(5,{})", subs.join(",")))
+            .expect("parsing");
+        bh.iter(|| {
+            let ms = policy.compile();
+            black_box(ms);
+        });
+    }
+
+    #[bench]
+    pub fn compile_deep(bh: &mut Bencher) {
+        let mut policy = "pk(K32)".to_owned();
+        for i in (0..32).rev() {
+            let frag = if i % 2 == 0 { "or" } else { "and" };
+            policy = format!("{}(pk(K{}),{})", frag, i, policy);
+        }
+        let policy = Concrete::<String>::from_str(&policy).expect("parsing");
         bh.iter(|| {
-            let pt = ParseTree::compile(&desc);
-            black_box(pt);
+            let ms = policy.compile();
+            black_box(ms);
         });
     }
 }
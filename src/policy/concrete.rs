@@ -17,17 +17,18 @@
 
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
-use std::{fmt, str};
+use std::collections::BTreeSet;
+use std::{cmp, error, fmt, str};
 
 use errstr;
 use expression::{self, FromTree};
 #[cfg(feature = "compiler")]
 use policy::compiler;
 #[cfg(feature = "compiler")]
-use policy::compiler::CompilerError;
+use policy::compiler::{Candidate, CompilerError, CompilerParams, DescriptorType};
+use {AbsLockTime, Error, MiniscriptKey, RelLockTime};
 #[cfg(feature = "compiler")]
-use Miniscript;
-use {Error, MiniscriptKey};
+use {Descriptor, Miniscript, ToPublicKey};
 
 /// Concrete policy which corresponds directly to a Miniscript structure,
 /// and whose disjunctions are annotated with satisfaction probabilities
@@ -36,10 +37,10 @@ use {Error, MiniscriptKey};
 pub enum Policy<Pk: MiniscriptKey> {
     /// A public key which must sign to satisfy the descriptor
     Key(Pk),
-    /// A relative locktime restriction
-    After(u32),
     /// An absolute locktime restriction
-    Older(u32),
+    After(AbsLockTime),
+    /// A relative locktime restriction
+    Older(RelLockTime),
     /// A SHA256 whose preimage must be provided to satisfy the descriptor
     Sha256(sha256::Hash),
     /// A SHA256d whose preimage must be provided to satisfy the descriptor
@@ -55,16 +56,255 @@ pub enum Policy<Pk: MiniscriptKey> {
     Or(Vec<(usize, Policy<Pk>)>),
     /// A set of descriptors, satisfactions must be provided for `k` of them
     Threshold(usize, Vec<Policy<Pk>>),
+    /// A set of descriptors along with their weights, satisfactions must be
+    /// provided for a subset of them whose weights add up to at least `k`.
+    /// Parsing and `check_policy` bound the total weight, which a policy
+    /// constructed directly must respect before it is lifted or compiled.
+    WeightedThreshold(usize, Vec<(usize, Policy<Pk>)>),
+}
+
+/// Detailed error type for concrete policies
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PolicyError {
+    /// `and` fragments need at least two arguments
+    InsufficientArgsforAnd,
+    /// `or` fragments need at least two arguments
+    InsufficientArgsforOr,
+    /// `or` fragments need at least one nonzero probability
+    ZeroProbabilityOr,
+    /// A threshold `k` must satisfy `1 <= k <= n`, for `n` subpolicies
+    IncorrectThresh(usize, usize),
+    /// A key may not appear more than once in a policy
+    DuplicatePubKeys,
+    /// A weighted threshold `k` must satisfy `1 <= k <= w`, for `w` the
+    /// total weight of the subpolicies
+    IncorrectWeightedThresh(usize, usize),
+    /// Subpolicies of weighted thresholds must have a nonzero weight
+    ZeroWeight,
+    /// A weighted threshold is compiled as a threshold in which each
+    /// subpolicy is repeated as many times as its weight, so once weights
+    /// above `k` count as `k` and common factors of `k` and the weights are
+    /// divided out, the total weight may not exceed 1000
+    WeightedThreshTooLarge(usize),
+}
+
+impl error::Error for PolicyError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        ""
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PolicyError::InsufficientArgsforAnd => {
+                f.write_str("And policy fragment must take at least 2 arguments")
+            }
+            PolicyError::InsufficientArgsforOr => {
+                f.write_str("Or policy fragment must take at least 2 arguments")
+            }
+            PolicyError::ZeroProbabilityOr => {
+                f.write_str("Or policy fragment must have a nonzero probability")
+            }
+            PolicyError::IncorrectThresh(k, n) => write!(
+                f,
+                "Threshold k must be between 1 and the number of subpolicies, \
+                 got {} of {}",
+                k, n
+            ),
+            PolicyError::DuplicatePubKeys => f.write_str("Policy contains duplicate keys"),
+            PolicyError::IncorrectWeightedThresh(k, w) => write!(
+                f,
+                "Weighted threshold k must be between 1 and the total weight \
+                 of the subpolicies, got {} of {}",
+                k, w
+            ),
+            PolicyError::ZeroWeight => {
+                f.write_str("Weighted threshold subpolicies must have a nonzero weight")
+            }
+            PolicyError::WeightedThreshTooLarge(w) => write!(
+                f,
+                "Weighted threshold total weight must be at most {}, got {}",
+                MAX_WEIGHTED_THRESH_TOTAL, w
+            ),
+        }
+    }
 }
 
 impl<Pk: MiniscriptKey> Policy<Pk> {
-    /// Compile the descriptor into an optimized `Miniscript` representation
+    /// Compile the descriptor into an optimized `Miniscript` representation.
+    /// Fails on the malformed fragments reported by `check_policy`, except
+    /// for duplicate keys which are allowed.
+    ///
+    /// Deeply nested policies are compiled approximately, so the result may
+    /// not be the cheapest Miniscript; see `compiler::best_compilation`.
     #[cfg(feature = "compiler")]
     pub fn compile(&self) -> Result<Miniscript<Pk>, CompilerError> {
+        self.compile_with_params(&CompilerParams::default())
+    }
+
+    /// Compile the descriptor into the `Miniscript` representation which
+    /// minimizes the cost described by `params`, up to the same
+    /// approximation as `compile`
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_params(
+        &self,
+        params: &CompilerParams,
+    ) -> Result<Miniscript<Pk>, CompilerError> {
+        self.check_fragments()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::best_compilation(&self.binarized(), params),
+        }
+    }
+
+    /// Compile the descriptor into up to `n` of the cheapest `Miniscript`
+    /// representations found by the compiler, with their costs. See
+    /// `compiler::best_compilations_n`.
+    #[cfg(feature = "compiler")]
+    pub fn compile_candidates(
+        &self,
+        params: &CompilerParams,
+        n: usize,
+    ) -> Result<Vec<Candidate<Pk>>, CompilerError> {
+        self.check_fragments()?;
         match self.is_safe_nonmalleable() {
             (false, _) => Err(CompilerError::TopLevelNonSafe),
             (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
-            _ => compiler::best_compilation(self),
+            _ => compiler::best_compilations_n(&self.binarized(), params, n),
+        }
+    }
+
+    /// Compile the policy into a descriptor of the given output type. The
+    /// compiler only considers Miniscripts within the script size and op
+    /// count limits of that output type, and segwit output types reject
+    /// uncompressed keys. As with `compile`, the Miniscript may not be the
+    /// cheapest one for deeply nested policies.
+    #[cfg(feature = "compiler")]
+    pub fn compile_to_descriptor(
+        &self,
+        desc_type: DescriptorType,
+    ) -> Result<Descriptor<Pk>, CompilerError> {
+        self.compile_to_descriptor_with_params(desc_type, &CompilerParams::default())
+    }
+
+    /// Compile the policy into a descriptor of the given output type, as
+    /// `compile_to_descriptor` does, minimizing the cost described by
+    /// `params`
+    #[cfg(feature = "compiler")]
+    pub fn compile_to_descriptor_with_params(
+        &self,
+        desc_type: DescriptorType,
+        params: &CompilerParams,
+    ) -> Result<Descriptor<Pk>, CompilerError> {
+        self.check_fragments()?;
+        if desc_type.is_segwit() && self.keys().iter().any(|pk| pk.is_uncompressed()) {
+            return Err(CompilerError::UncompressedKeyInSegwit);
+        }
+        let ms = match self.is_safe_nonmalleable() {
+            (false, _) => return Err(CompilerError::TopLevelNonSafe),
+            (_, false) => return Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::best_compilation_for(&self.binarized(), desc_type, params)?,
+        };
+        Ok(match desc_type {
+            DescriptorType::Bare => Descriptor::Bare(ms),
+            DescriptorType::Sh => Descriptor::Sh(ms),
+            DescriptorType::Wsh => Descriptor::Wsh(ms),
+            DescriptorType::ShWsh => Descriptor::ShWsh(ms),
+        })
+    }
+
+    /// Helper function for `compile` to split `and` and `or` fragments
+    /// with more than two arguments into nested binary ones, which is the
+    /// only form the compiler accepts
+    #[cfg(feature = "compiler")]
+    pub(crate) fn binarized(&self) -> Policy<Pk> {
+        match *self {
+            Policy::And(ref subs) if subs.len() > 2 => Policy::And(vec![
+                subs[0].binarized(),
+                Policy::And(subs[1..].to_vec()).binarized(),
+            ]),
+            Policy::And(ref subs) => Policy::And(subs.iter().map(Policy::binarized).collect()),
+            Policy::Or(ref subs) if subs.len() > 2 => {
+                let mut rest = subs[1..].to_vec();
+                let rest_prob = rest.iter().map(|&(prob, _)| prob).sum();
+                // The probabilities within a branch which is never taken
+                // are irrelevant, but must not all be zero
+                if rest_prob == 0 {
+                    for sub in &mut rest {
+                        sub.0 = 1;
+                    }
+                }
+                Policy::Or(vec![
+                    (subs[0].0, subs[0].1.binarized()),
+                    (rest_prob, Policy::Or(rest).binarized()),
+                ])
+            }
+            Policy::Or(ref subs) => Policy::Or(
+                subs.iter()
+                    .map(|&(prob, ref sub)| (prob, sub.binarized()))
+                    .collect(),
+            ),
+            Policy::Threshold(k, ref subs) => {
+                Policy::Threshold(k, subs.iter().map(Policy::binarized).collect())
+            }
+            Policy::WeightedThreshold(k, ref subs) => {
+                let (k, subs) = expand_weights(k, subs);
+                Policy::Threshold(k, subs).binarized()
+            }
+            ref leaf => leaf.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "compiler")]
+impl<Pk: MiniscriptKey + ToPublicKey> Policy<Pk> {
+    /// Compile the policy into each of the standard output types `Wsh`,
+    /// `ShWsh` and `Sh`, and return the cheapest descriptor. The cost of an
+    /// output type is the weight of its scriptpubkey plus the maximum
+    /// weight of a satisfaction; these are returned for every output type
+    /// compared, along with the reason why compiling for it failed if it
+    /// did. Ties are broken in the order above. The costs are those of the
+    /// compilations found, which may not be the cheapest, see `compile`.
+    pub fn compile_to_cheapest_descriptor(
+        &self,
+    ) -> Result<
+        (
+            Descriptor<Pk>,
+            Vec<(DescriptorType, Result<usize, CompilerError>)>,
+        ),
+        CompilerError,
+    > {
+        let mut best: Option<(Descriptor<Pk>, usize)> = None;
+        let mut first_err = None;
+        let mut weights = Vec::with_capacity(3);
+        for &desc_type in &[
+            DescriptorType::Wsh,
+            DescriptorType::ShWsh,
+            DescriptorType::Sh,
+        ] {
+            match self.compile_to_descriptor(desc_type) {
+                Ok(desc) => {
+                    let weight = 4 * desc.script_pubkey().len() + desc.max_satisfaction_weight();
+                    weights.push((desc_type, Ok(weight)));
+                    if best.as_ref().map(|&(_, w)| weight < w).unwrap_or(true) {
+                        best = Some((desc, weight));
+                    }
+                }
+                Err(e) => {
+                    weights.push((desc_type, Err(e)));
+                    first_err = first_err.or(Some(e));
+                }
+            }
+        }
+        match best {
+            Some((desc, _)) => Ok((desc, weights)),
+            None => Err(first_err.unwrap()),
         }
     }
 }
@@ -92,6 +332,12 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     .collect();
                 new_subs.map(|ok| Policy::Threshold(k, ok))
             }
+            Policy::WeightedThreshold(k, ref subs) => Ok(Policy::WeightedThreshold(
+                k,
+                subs.iter()
+                    .map(|&(weight, ref sub)| Ok((weight, sub.translate_pk(&mut translatefpk)?)))
+                    .collect::<Result<Vec<(usize, Policy<Q>)>, E>>()?,
+            )),
             Policy::And(ref subs) => Ok(Policy::And(
                 subs.iter()
                     .map(|sub| sub.translate_pk(&mut translatefpk))
@@ -129,9 +375,11 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                             non_mall_count + non_mall as usize,
                         )
                     });
+                // `k` may exceed the number of subpolicies if the policy
+                // has not been checked
                 (
-                    safe_count >= (subs.len() - k + 1),
-                    non_mall_count == subs.len() && safe_count >= (subs.len() - k),
+                    safe_count > subs.len().saturating_sub(k),
+                    non_mall_count == subs.len() && safe_count >= subs.len().saturating_sub(k),
                 )
             }
             Policy::And(ref subs) => {
@@ -151,10 +399,434 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     });
                 (all_safe, atleast_one_safe && all_non_mall)
             }
+            Policy::WeightedThreshold(k, ref subs) => {
+                let (k, subs) = expand_weights(k, subs);
+                Policy::Threshold(k, subs).is_safe_nonmalleable()
+            }
+        }
+    }
+
+    /// Explains why `is_safe_nonmalleable` does not return `(true, true)`,
+    /// by listing the subpolicies responsible. The list is empty exactly
+    /// when the policy is safe and can be compiled non-malleably.
+    pub fn safety_issues(&self) -> Vec<SafetyIssue<Pk>> {
+        let mut ret = vec![];
+        let (safe, non_mall) = self.is_safe_nonmalleable();
+        if !safe {
+            self.push_unsafe_issues(&mut ret);
+        }
+        if !non_mall {
+            self.push_malleability_issues(&mut ret);
+        }
+        ret
+    }
+
+    /// Helper function for `safety_issues` to find the smallest subpolicies
+    /// which make an unsafe policy unsafe
+    fn push_unsafe_issues(&self, ret: &mut Vec<SafetyIssue<Pk>>) {
+        match *self {
+            Policy::Or(ref subs) => {
+                for &(_, ref sub) in subs {
+                    if !sub.is_safe_nonmalleable().0 {
+                        sub.push_unsafe_issues(ret);
+                    }
+                }
+            }
+            _ => ret.push(SafetyIssue::NoSignature(self.clone())),
+        }
+    }
+
+    /// Helper function for `safety_issues` to find the smallest subpolicies
+    /// which cannot be satisfied non-malleably
+    fn push_malleability_issues(&self, ret: &mut Vec<SafetyIssue<Pk>>) {
+        let subs: Vec<&Policy<Pk>> = match *self {
+            Policy::And(ref subs) => subs.iter().collect(),
+            Policy::Or(ref subs) => {
+                if subs
+                    .iter()
+                    .all(|&(_, ref sub)| !sub.is_safe_nonmalleable().0)
+                {
+                    ret.push(SafetyIssue::MalleableOr(self.clone()));
+                    return;
+                }
+                subs.iter().map(|&(_, ref sub)| sub).collect()
+            }
+            Policy::Threshold(k, ref subs) => {
+                let safe_count = subs
+                    .iter()
+                    .filter(|sub| sub.is_safe_nonmalleable().0)
+                    .count();
+                if safe_count + k < subs.len() {
+                    ret.push(SafetyIssue::MalleableThreshold(self.clone()));
+                    return;
+                }
+                subs.iter().collect()
+            }
+            Policy::WeightedThreshold(k, ref subs) => {
+                let (k, weights) = reduced_weights(k, subs);
+                let safe_weight: usize = subs
+                    .iter()
+                    .zip(&weights)
+                    .filter(|&(&(_, ref sub), _)| sub.is_safe_nonmalleable().0)
+                    .map(|(_, &weight)| weight)
+                    .sum();
+                if safe_weight + k < weights.iter().sum() {
+                    ret.push(SafetyIssue::MalleableThreshold(self.clone()));
+                    return;
+                }
+                subs.iter().map(|&(_, ref sub)| sub).collect()
+            }
+            _ => vec![],
+        };
+        for sub in subs {
+            if !sub.is_safe_nonmalleable().1 {
+                sub.push_malleability_issues(ret);
+            }
         }
     }
 }
 
+/// A subpolicy which prevents a policy from being compiled to a safe and
+/// non-malleable Miniscript, as reported by `Policy::safety_issues`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SafetyIssue<Pk: MiniscriptKey> {
+    /// The subpolicy can be satisfied without any signature, so anyone who
+    /// sees the spending transaction can redirect the coins
+    NoSignature(Policy<Pk>),
+    /// None of the branches of this `or` requires a signature, so a third
+    /// party can replace the branch used by another one
+    MalleableOr(Policy<Pk>),
+    /// More than `k` subpolicies (or, for a weighted threshold, more than
+    /// `k` of weight) of this threshold can be satisfied without a
+    /// signature, so a third party can change which ones are used
+    MalleableThreshold(Policy<Pk>),
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for SafetyIssue<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SafetyIssue::NoSignature(ref pol) => write!(
+                f,
+                "{} can be satisfied without a signature; \
+                 require a key as well, e.g. and(pk(...),{})",
+                pol, pol
+            ),
+            SafetyIssue::MalleableOr(ref pol) => write!(
+                f,
+                "no branch of {} requires a signature, so third parties \
+                 can change the branch used; require a key in all but one branch",
+                pol
+            ),
+            SafetyIssue::MalleableThreshold(ref pol) => write!(
+                f,
+                "too many subpolicies of {} can be satisfied without a \
+                 signature, so third parties can change those used; require a \
+                 key in all but k of them",
+                pol
+            ),
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Checks the policy for fragments which can be parsed but which make
+    /// no sense to compile: `and` and `or` with fewer than two arguments,
+    /// `or` whose probabilities are all zero, thresholds which are zero or
+    /// larger than their number of subpolicies, weighted thresholds which
+    /// are zero or larger than the total weight of their subpolicies or
+    /// which have subpolicies of zero weight, and keys which appear more
+    /// than once. Zero timelocks cannot be represented, and are rejected
+    /// when the policy is constructed.
+    pub fn check_policy(&self) -> Result<(), PolicyError> {
+        self.check_fragments()?;
+        let mut keys = BTreeSet::new();
+        if self.keys().into_iter().all(|pk| keys.insert(pk)) {
+            Ok(())
+        } else {
+            Err(PolicyError::DuplicatePubKeys)
+        }
+    }
+
+    /// Helper function for `check_policy` which checks everything but
+    /// duplicate keys. Policies failing this check cannot be compiled.
+    fn check_fragments(&self) -> Result<(), PolicyError> {
+        match *self {
+            Policy::And(ref subs) => {
+                if subs.len() < 2 {
+                    return Err(PolicyError::InsufficientArgsforAnd);
+                }
+                for sub in subs {
+                    sub.check_fragments()?;
+                }
+            }
+            Policy::Or(ref subs) => {
+                if subs.len() < 2 {
+                    return Err(PolicyError::InsufficientArgsforOr);
+                }
+                if total_prob(subs) == 0 {
+                    return Err(PolicyError::ZeroProbabilityOr);
+                }
+                for &(_, ref sub) in subs {
+                    sub.check_fragments()?;
+                }
+            }
+            Policy::Threshold(k, ref subs) => {
+                if k == 0 || k > subs.len() {
+                    return Err(PolicyError::IncorrectThresh(k, subs.len()));
+                }
+                for sub in subs {
+                    sub.check_fragments()?;
+                }
+            }
+            Policy::WeightedThreshold(k, ref subs) => {
+                if subs.iter().any(|&(weight, _)| weight == 0) {
+                    return Err(PolicyError::ZeroWeight);
+                }
+                if k == 0 || k > total_prob(subs) {
+                    return Err(PolicyError::IncorrectWeightedThresh(k, total_prob(subs)));
+                }
+                let total: usize = reduced_weights(k, subs).1.iter().sum();
+                if total > MAX_WEIGHTED_THRESH_TOTAL {
+                    return Err(PolicyError::WeightedThreshTooLarge(total));
+                }
+                for &(_, ref sub) in subs {
+                    sub.check_fragments()?;
+                }
+            }
+            Policy::Key(..)
+            | Policy::After(..)
+            | Policy::Older(..)
+            | Policy::Sha256(..)
+            | Policy::Hash256(..)
+            | Policy::Ripemd160(..)
+            | Policy::Hash160(..) => {}
+        }
+        Ok(())
+    }
+
+    /// Returns all the keys of the policy, in order of appearance and with
+    /// repetitions
+    pub fn keys(&self) -> Vec<&Pk> {
+        match *self {
+            Policy::Key(ref pk) => vec![pk],
+            Policy::And(ref subs) | Policy::Threshold(_, ref subs) => {
+                subs.iter().flat_map(Policy::keys).collect()
+            }
+            Policy::Or(ref subs) | Policy::WeightedThreshold(_, ref subs) => {
+                subs.iter().flat_map(|&(_, ref sub)| sub.keys()).collect()
+            }
+            Policy::After(..)
+            | Policy::Older(..)
+            | Policy::Sha256(..)
+            | Policy::Hash256(..)
+            | Policy::Ripemd160(..)
+            | Policy::Hash160(..) => vec![],
+        }
+    }
+
+    /// Flatten out trees of `And`s and `Or`s, removing duplicate
+    /// subpolicies; replace `And`s and `Or`s of a single subpolicy by that
+    /// subpolicy, and thresholds of 1 or of all their subpolicies by `Or`s
+    /// and `And`s. When a nested `Or` is flattened, the probabilities of
+    /// its branches are scaled by its own probability, so that every leaf
+    /// keeps the same overall likelihood; duplicate branches have their
+    /// probabilities added up. Likewise duplicate subpolicies of weighted
+    /// thresholds have their weights added up, and weighted thresholds
+    /// whose weights are all 1 are replaced by thresholds.
+    pub fn normalized(self) -> Policy<Pk> {
+        match self {
+            Policy::And(subs) => {
+                let mut ret_subs = Vec::with_capacity(subs.len());
+                for sub in subs {
+                    let new_subs = match sub.normalized() {
+                        Policy::And(and_subs) => and_subs,
+                        x => vec![x],
+                    };
+                    for x in new_subs {
+                        if !ret_subs.contains(&x) {
+                            ret_subs.push(x);
+                        }
+                    }
+                }
+                if ret_subs.len() == 1 {
+                    ret_subs.pop().unwrap()
+                } else {
+                    Policy::And(ret_subs)
+                }
+            }
+            Policy::Or(subs) => {
+                let subs: Vec<(usize, Policy<Pk>)> = subs
+                    .into_iter()
+                    .map(|(prob, sub)| (prob, sub.normalized()))
+                    .collect();
+                let mut ret_subs = match merge_or_branches(&subs) {
+                    Some(ret_subs) => ret_subs,
+                    // The probabilities are too large to be brought to a
+                    // common denominator, so leave the branches unmerged
+                    None => return Policy::Or(subs),
+                };
+                let divisor = ret_subs.iter().fold(0, |acc, &(prob, _)| gcd(acc, prob));
+                if divisor > 1 {
+                    for sub in &mut ret_subs {
+                        sub.0 /= divisor;
+                    }
+                }
+
+                if ret_subs.len() == 1 {
+                    ret_subs.pop().unwrap().1
+                } else {
+                    Policy::Or(ret_subs)
+                }
+            }
+            Policy::Threshold(k, subs) => {
+                let subs: Vec<Policy<Pk>> = subs.into_iter().map(Policy::normalized).collect();
+                if k == subs.len() {
+                    Policy::And(subs).normalized()
+                } else if k == 1 {
+                    Policy::Or(subs.into_iter().map(|sub| (1, sub)).collect()).normalized()
+                } else {
+                    Policy::Threshold(k, subs)
+                }
+            }
+            Policy::WeightedThreshold(k, subs) => {
+                let mut ret_subs = Vec::with_capacity(subs.len());
+                for (weight, sub) in subs {
+                    let sub = sub.normalized();
+                    if add_or_branch(&mut ret_subs, weight, sub.clone()).is_none() {
+                        // Too heavy to merge with the identical branch
+                        ret_subs.push((weight, sub));
+                    }
+                }
+                if ret_subs.iter().all(|&(weight, _)| weight == 1) {
+                    Policy::Threshold(k, ret_subs.into_iter().map(|(_, sub)| sub).collect())
+                        .normalized()
+                } else {
+                    Policy::WeightedThreshold(k, ret_subs)
+                }
+            }
+            x => x,
+        }
+    }
+}
+
+/// Maximum total weight of the subpolicies of a weighted threshold, once
+/// reduced by `reduced_weights`
+const MAX_WEIGHTED_THRESH_TOTAL: usize = 1000;
+
+/// Helper function to reduce the weights of a weighted threshold, returning
+/// the `k` and the weights of the smallest equivalent one: weights above
+/// `k` count as `k`, and then `k` and the weights are divided by their
+/// greatest common divisor
+pub(crate) fn reduced_weights<Pk: MiniscriptKey>(
+    k: usize,
+    subs: &[(usize, Policy<Pk>)],
+) -> (usize, Vec<usize>) {
+    let weights: Vec<usize> = subs
+        .iter()
+        .map(|&(weight, _)| cmp::min(weight, k))
+        .collect();
+    let div = weights.iter().fold(k, |div, &weight| gcd(div, weight));
+    match k.checked_div(div) {
+        Some(k) => (k, weights.iter().map(|weight| weight / div).collect()),
+        None => (k, weights),
+    }
+}
+
+/// Helper function to expand a weighted threshold into the `k` and the
+/// subpolicies of an equivalent threshold, in which each subpolicy is
+/// repeated as many times as its reduced weight
+fn expand_weights<Pk: MiniscriptKey>(
+    k: usize,
+    subs: &[(usize, Policy<Pk>)],
+) -> (usize, Vec<Policy<Pk>>) {
+    let (k, weights) = reduced_weights(k, subs);
+    let subs = subs
+        .iter()
+        .zip(weights)
+        .flat_map(|(sub, weight)| (0..weight).map(move |_| sub.1.clone()))
+        .collect();
+    (k, subs)
+}
+
+/// Helper function for `normalized` to compute the total probability of
+/// the branches of an `Or`, or the total weight of a weighted threshold,
+/// saturating on overflow
+fn total_prob<Pk: MiniscriptKey>(subs: &[(usize, Policy<Pk>)]) -> usize {
+    subs.iter()
+        .fold(0, |total: usize, &(prob, _)| total.saturating_add(prob))
+}
+
+/// Helper function for `normalized` to flatten the `Or`s among the branches
+/// of an `Or` and merge identical branches, bringing the probabilities of
+/// all flattened `Or`s to a common denominator. Returns `None` if any
+/// probability overflows.
+fn merge_or_branches<Pk: MiniscriptKey>(
+    subs: &[(usize, Policy<Pk>)],
+) -> Option<Vec<(usize, Policy<Pk>)>> {
+    let checked_total = |or_subs: &[(usize, Policy<Pk>)]| -> Option<usize> {
+        let mut total = 0usize;
+        for &(prob, _) in or_subs {
+            total = total.checked_add(prob)?;
+        }
+        Some(total)
+    };
+
+    let mut denom = 1;
+    for &(_, ref sub) in subs {
+        if let Policy::Or(ref or_subs) = *sub {
+            let total = checked_total(or_subs)?;
+            if total > 0 {
+                denom = lcm(denom, total)?;
+            }
+        }
+    }
+
+    let mut ret = Vec::with_capacity(subs.len());
+    for &(prob, ref sub) in subs {
+        let total = match *sub {
+            Policy::Or(ref or_subs) => checked_total(or_subs)?,
+            _ => 0,
+        };
+        match *sub {
+            Policy::Or(ref or_subs) if total > 0 => {
+                let scale = prob.checked_mul(denom / total)?;
+                for &(or_prob, ref or_sub) in or_subs {
+                    add_or_branch(&mut ret, or_prob.checked_mul(scale)?, or_sub.clone())?;
+                }
+            }
+            _ => add_or_branch(&mut ret, prob.checked_mul(denom)?, sub.clone())?,
+        }
+    }
+    Some(ret)
+}
+
+/// Helper function for `normalized` to add a branch to an `Or` or a
+/// weighted threshold, merging it with an identical one if there is any.
+/// Returns `None` if the merged probability overflows.
+fn add_or_branch<Pk: MiniscriptKey>(
+    subs: &mut Vec<(usize, Policy<Pk>)>,
+    prob: usize,
+    sub: Policy<Pk>,
+) -> Option<()> {
+    match subs.iter_mut().find(|&&mut (_, ref x)| *x == sub) {
+        Some(existing) => existing.0 = existing.0.checked_add(prob)?,
+        None => subs.push((prob, sub)),
+    }
+    Some(())
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn lcm(a: usize, b: usize) -> Option<usize> {
+    (a / gcd(a, b)).checked_mul(b)
+}
+
 impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -192,6 +864,13 @@ impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
                 }
                 f.write_str(")")
             }
+            Policy::WeightedThreshold(k, ref subs) => {
+                write!(f, "wthresh({}", k)?;
+                for sub in subs {
+                    write!(f, ",{}@{:?}", sub.0, sub.1)?;
+                }
+                f.write_str(")")
+            }
         }
     }
 }
@@ -233,6 +912,13 @@ impl<Pk: MiniscriptKey> fmt::Display for Policy<Pk> {
                 }
                 f.write_str(")")
             }
+            Policy::WeightedThreshold(k, ref subs) => {
+                write!(f, "wthresh({}", k)?;
+                for sub in subs {
+                    write!(f, ",{}@{}", sub.0, sub.1)?;
+                }
+                f.write_str(")")
+            }
         }
     }
 }
@@ -294,10 +980,14 @@ where
         match (frag_name, top.args.len() as u32) {
             ("pk", 1) => expression::terminal(&top.args[0], |pk| Pk::from_str(pk).map(Policy::Key)),
             ("after", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Policy::After)
+                expression::parse_num(x)
+                    .and_then(AbsLockTime::from_consensus)
+                    .map(Policy::After)
             }),
             ("older", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Policy::Older)
+                expression::parse_num(x)
+                    .and_then(RelLockTime::from_consensus)
+                    .map(Policy::Older)
             }),
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256::Hash::from_hex(x).map(Policy::Sha256)
@@ -347,6 +1037,24 @@ where
                 }
                 Ok(Policy::Threshold(thresh as usize, subs))
             }
+            ("wthresh", nsubs) => {
+                if nsubs < 2 || !top.args[0].args.is_empty() {
+                    return Err(errstr(top.name));
+                }
+
+                let thresh = expression::parse_num(top.args[0].name)?;
+                let mut subs = Vec::with_capacity(top.args.len() - 1);
+                for arg in &top.args[1..] {
+                    subs.push(Policy::from_tree_prob(arg, true)?);
+                }
+                let total: usize = reduced_weights(thresh as usize, &subs).1.iter().sum();
+                if total > MAX_WEIGHTED_THRESH_TOTAL {
+                    return Err(Error::PolicyError(PolicyError::WeightedThreshTooLarge(
+                        total,
+                    )));
+                }
+                Ok(Policy::WeightedThreshold(thresh as usize, subs))
+            }
             _ => Err(errstr(top.name)),
         }
         .map(|res| (frag_prob, res))
@@ -362,3 +1070,352 @@ where
         Policy::from_tree_prob(top, false).map(|(_, result)| result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    type StringPolicy = Policy<String>;
+
+    #[test]
+    fn check_policy() {
+        let check = |s: &str| StringPolicy::from_str(s).unwrap().check_policy();
+
+        assert_eq!(check("or(pk(A),and(pk(B),older(100)))"), Ok(()));
+        assert_eq!(check("and(pk(A),pk(B),pk(C))"), Ok(()));
+        assert_eq!(
+            check("thresh(0,pk(A),pk(B))"),
+            Err(PolicyError::IncorrectThresh(0, 2))
+        );
+        assert_eq!(
+            Policy::Threshold(3, vec![Policy::Key("A".to_owned())]).check_policy(),
+            Err(PolicyError::IncorrectThresh(3, 1))
+        );
+        assert_eq!(
+            check("and(pk(A))"),
+            Err(PolicyError::InsufficientArgsforAnd)
+        );
+        assert_eq!(check("or(pk(A))"), Err(PolicyError::InsufficientArgsforOr));
+        assert_eq!(
+            check("or(0@pk(A),0@pk(B))"),
+            Err(PolicyError::ZeroProbabilityOr)
+        );
+        assert_eq!(
+            check("or(pk(A),and(pk(B),pk(A)))"),
+            Err(PolicyError::DuplicatePubKeys)
+        );
+        assert!(StringPolicy::from_str("older(0)").is_err());
+        assert_eq!(
+            check("wthresh(5,2@pk(A),pk(B),pk(C))"),
+            Err(PolicyError::IncorrectWeightedThresh(5, 4))
+        );
+        assert_eq!(
+            check("wthresh(1,0@pk(A),pk(B))"),
+            Err(PolicyError::ZeroWeight)
+        );
+        assert_eq!(check("wthresh(1000,999@pk(A),pk(B))"), Ok(()));
+        let heavy = vec![
+            (1000, Policy::Key("A".to_owned())),
+            (1, Policy::Key("B".to_owned())),
+        ];
+        assert_eq!(
+            Policy::WeightedThreshold(1001, heavy).check_policy(),
+            Err(PolicyError::WeightedThreshTooLarge(1001))
+        );
+        match StringPolicy::from_str("wthresh(1001,1000@pk(A),pk(B))") {
+            Err(Error::PolicyError(PolicyError::WeightedThreshTooLarge(1001))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn normalized() {
+        let norm = |s: &str| StringPolicy::from_str(s).unwrap().normalized().to_string();
+
+        assert_eq!(
+            norm("and(pk(A),and(pk(B),and(pk(C),pk(A))))"),
+            "and(pk(A),pk(B),pk(C))"
+        );
+        assert_eq!(norm("and(pk(A))"), "pk(A)");
+        assert_eq!(norm("or(and(pk(A)),pk(A))"), "pk(A)");
+        assert_eq!(norm("thresh(2,pk(A),pk(B))"), "and(pk(A),pk(B))");
+        assert_eq!(norm("thresh(1,pk(A),pk(B))"), "or(1@pk(A),1@pk(B))");
+        assert_eq!(
+            norm("thresh(2,pk(A),pk(B),pk(C))"),
+            "thresh(2,pk(A),pk(B),pk(C))"
+        );
+        // A is taken with probability 1/4, B and C with 3/8 each
+        assert_eq!(
+            norm("or(1@pk(A),3@or(1@pk(B),1@pk(C)))"),
+            "or(2@pk(A),3@pk(B),3@pk(C))"
+        );
+        // B is taken with probability 1/2 * 1/3 + 1/2
+        assert_eq!(norm("or(or(1@pk(B),2@pk(C)),pk(B))"), "or(2@pk(B),1@pk(C))");
+        assert_eq!(
+            norm("wthresh(2,pk(A),pk(B),pk(C))"),
+            "thresh(2,pk(A),pk(B),pk(C))"
+        );
+        assert_eq!(
+            norm("wthresh(2,pk(A),and(pk(B)),pk(A))"),
+            "wthresh(2,2@pk(A),1@pk(B))"
+        );
+
+        // Probabilities which overflow when merged are left unmerged
+        let policy = "or(\
+                      1@or(4294967295@pk(A),1@pk(B)),\
+                      1@or(4294967294@pk(C),1@pk(D)),\
+                      1@or(4294967292@pk(E),1@pk(F))\
+                      )";
+        assert_eq!(norm(policy), policy);
+        let policy = "or(1@or(4294967295@pk(A),1@pk(B)),1@or(4294967294@pk(A),1@pk(C)))";
+        assert_eq!(norm(policy), policy);
+    }
+
+    #[test]
+    fn weighted_threshold() {
+        use policy::Liftable;
+
+        let policy = StringPolicy::from_str("wthresh(3,2@pk(A),pk(B),pk(C),pk(D))").unwrap();
+        assert_eq!(
+            policy.to_string(),
+            "wthresh(3,2@pk(A),1@pk(B),1@pk(C),1@pk(D))"
+        );
+        assert_eq!(policy.check_policy(), Ok(()));
+        assert_eq!(policy.keys().len(), 4);
+
+        // A counts as two votes
+        let expected = StringPolicy::from_str(
+            "or(and(pk(A),or(pk(B),pk(C),pk(D))),thresh(3,pk(B),pk(C),pk(D)))",
+        )
+        .unwrap();
+        assert!(policy.lift().is_equivalent(&expected.lift()).unwrap());
+
+        assert!(StringPolicy::from_str("wthresh(1)").is_err());
+        assert!(StringPolicy::from_str("wthresh(1,pk(A),or(2@pk(B),pk(C)))").is_ok());
+        assert_eq!(
+            StringPolicy::from_str("wthresh(2,pk(A),2@and(older(100),after(100)))")
+                .unwrap()
+                .safety_issues(),
+            vec![SafetyIssue::NoSignature(
+                StringPolicy::from_str("wthresh(2,pk(A),2@and(older(100),after(100)))").unwrap()
+            )]
+        );
+        assert_eq!(
+            StringPolicy::from_str("and(pk(A),wthresh(1,pk(B),2@older(100),after(100)))")
+                .unwrap()
+                .safety_issues()
+                .len(),
+            1
+        );
+
+        // Weights above `k` count as `k`, and common factors are divided out
+        let policy = StringPolicy::from_str("wthresh(1,4294967295@pk(A),pk(B))").unwrap();
+        assert_eq!(
+            policy.lift(),
+            StringPolicy::from_str("or(pk(A),pk(B))").unwrap().lift()
+        );
+        let policy = StringPolicy::from_str("wthresh(2,100000@pk(A),pk(B))").unwrap();
+        assert_eq!(policy.check_policy(), Ok(()));
+        assert!(policy
+            .lift()
+            .is_equivalent(&StringPolicy::from_str("pk(A)").unwrap().lift())
+            .unwrap());
+        let policy = StringPolicy::from_str("wthresh(4,2@pk(A),2@pk(B),2@pk(C))").unwrap();
+        let equivalent = StringPolicy::from_str("thresh(2,pk(A),pk(B),pk(C))").unwrap();
+        assert_eq!(policy.lift(), equivalent.lift());
+
+        #[cfg(feature = "compiler")]
+        {
+            let policy = StringPolicy::from_str("wthresh(3,2@pk(A),pk(B),pk(C),pk(D))").unwrap();
+            let ms = policy.compile().unwrap();
+            assert!(ms.lift().is_equivalent(&policy.lift()).unwrap());
+
+            let policy = StringPolicy::from_str("wthresh(2,100000@pk(A),pk(B))").unwrap();
+            let ms = policy.compile().unwrap();
+            assert!(ms.lift().is_equivalent(&policy.lift()).unwrap());
+            assert_eq!(
+                StringPolicy::from_str("wthresh(4,2@pk(A),2@pk(B),2@pk(C))")
+                    .unwrap()
+                    .compile(),
+                equivalent.compile()
+            );
+        }
+    }
+
+    #[test]
+    fn safety_issues() {
+        let issues = |s: &str| {
+            StringPolicy::from_str(s)
+                .unwrap()
+                .safety_issues()
+                .iter()
+                .map(|issue| match *issue {
+                    SafetyIssue::NoSignature(ref pol) => format!("nosig {}", pol),
+                    SafetyIssue::MalleableOr(ref pol) => format!("or {}", pol),
+                    SafetyIssue::MalleableThreshold(ref pol) => format!("thresh {}", pol),
+                })
+                .collect::<Vec<_>>()
+        };
+        let h = "1111111111111111111111111111111111111111111111111111111111111111";
+
+        assert!(issues("or(pk(A),and(pk(B),older(100)))").is_empty());
+        assert_eq!(
+            issues("or(pk(A),and(older(100),after(100)))"),
+            vec!["nosig and(older(100),after(100))"]
+        );
+        assert_eq!(
+            issues(&format!("or(pk(A),or(sha256({}),older(100)))", h)),
+            vec![
+                format!("nosig sha256({})", h),
+                "nosig older(100)".to_owned(),
+                format!("or or(1@sha256({}),1@older(100))", h),
+            ]
+        );
+        assert_eq!(
+            issues(&format!("and(pk(A),or(sha256({}),older(100)))", h)),
+            vec![format!("or or(1@sha256({}),1@older(100))", h)]
+        );
+        assert_eq!(
+            issues("and(pk(A),thresh(1,pk(B),older(100),after(100)))"),
+            vec!["thresh thresh(1,pk(B),older(100),after(100))"]
+        );
+        assert_eq!(
+            issues("and(pk(A),thresh(2,pk(B),older(100),after(100)))"),
+            Vec::<String>::new()
+        );
+        // Both unsafe and malleable
+        assert_eq!(
+            issues("or(older(100),after(100))"),
+            vec![
+                "nosig older(100)",
+                "nosig after(100)",
+                "or or(1@older(100),1@after(100))"
+            ]
+        );
+        // Thresholds larger than their number of subpolicies are not
+        // rejected here, but must not panic either
+        assert_eq!(
+            issues("wthresh(5,2@pk(A),after(100))"),
+            Vec::<String>::new()
+        );
+        let policy = Policy::Threshold(
+            3,
+            vec![Policy::Key("A".to_owned()), Policy::Key("B".to_owned())],
+        );
+        assert_eq!(policy.safety_issues(), vec![]);
+        assert!(policy.check_policy().is_err());
+    }
+
+    #[cfg(feature = "compiler")]
+    #[test]
+    fn compile_checked() {
+        use policy::compiler::CompilerError;
+        use policy::Liftable;
+
+        let policy = StringPolicy::from_str("thresh(0,pk(A),pk(B))").unwrap();
+        assert_eq!(
+            policy.compile(),
+            Err(CompilerError::PolicyError(PolicyError::IncorrectThresh(
+                0, 2
+            )))
+        );
+
+        let policy = StringPolicy::from_str(
+            "and(pk(A),pk(B),or(9@pk(C),1@and(pk(D),older(100)),1@and(pk(D),after(1000))))",
+        )
+        .unwrap();
+        let ms = policy.compile().unwrap();
+        assert!(ms.lift().is_equivalent(&policy.lift()).unwrap());
+    }
+
+    #[cfg(feature = "compiler")]
+    #[test]
+    fn compile_to_descriptor() {
+        use bitcoin::{self, secp256k1};
+        use policy::compiler::{CompilerError, CostModel, DescriptorType};
+
+        let secp = secp256k1::Secp256k1::signing_only();
+        let keys: Vec<bitcoin::PublicKey> = (1..17)
+            .map(|i| bitcoin::PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(
+                    &secp,
+                    &secp256k1::SecretKey::from_slice(&[i; 32]).unwrap(),
+                ),
+                compressed: true,
+            })
+            .collect();
+        let thresh = |k: usize, keys: &[bitcoin::PublicKey]| {
+            Policy::Threshold(k, keys.iter().cloned().map(Policy::Key).collect())
+        };
+
+        let policy = thresh(2, &keys[0..3]);
+        match policy.compile_to_descriptor(DescriptorType::ShWsh).unwrap() {
+            Descriptor::ShWsh(ref ms) => assert_eq!(*ms, policy.compile().unwrap()),
+            desc => panic!("unexpected descriptor {}", desc),
+        }
+
+        // The cost model is configurable as for `compile_with_params`
+        let policy = Policy::Or(vec![
+            (127, Policy::Key(keys[0])),
+            (
+                1,
+                Policy::And(vec![
+                    Policy::Key(keys[1]),
+                    Policy::Or(vec![
+                        (1, Policy::Key(keys[2])),
+                        (
+                            9,
+                            Policy::And(vec![Policy::Key(keys[3]), Policy::Key(keys[4])]),
+                        ),
+                    ]),
+                ]),
+            ),
+        ]);
+        let params = CompilerParams {
+            cost_model: CostModel::WorstCase,
+            ..CompilerParams::default()
+        };
+        let ms = policy.compile_with_params(&params).unwrap();
+        assert!(ms != policy.compile().unwrap());
+        assert_eq!(
+            policy.compile_to_descriptor_with_params(DescriptorType::Wsh, &params),
+            Ok(Descriptor::Wsh(ms))
+        );
+        let (desc, weights) = policy.compile_to_cheapest_descriptor().unwrap();
+        match desc {
+            Descriptor::Wsh(..) => {}
+            desc => panic!("unexpected descriptor {}", desc),
+        }
+        assert_eq!(weights.len(), 3);
+        assert_eq!(weights[0].0, DescriptorType::Wsh);
+        let wsh_weight = weights[0].1.unwrap();
+        assert!(weights[1..].iter().all(|w| w.1.unwrap() > wsh_weight));
+
+        // 16 keys do not fit in a 520-byte redeem script
+        let policy = thresh(8, &keys);
+        assert_eq!(
+            policy.compile_to_descriptor(DescriptorType::Sh),
+            Err(CompilerError::ScriptSizeExceeded)
+        );
+        let (_, weights) = policy.compile_to_cheapest_descriptor().unwrap();
+        assert_eq!(
+            weights[2],
+            (DescriptorType::Sh, Err(CompilerError::ScriptSizeExceeded))
+        );
+
+        // Segwit does not allow uncompressed keys
+        let mut uncompressed = keys[0..3].to_vec();
+        uncompressed[0].compressed = false;
+        let policy = thresh(2, &uncompressed);
+        assert_eq!(
+            policy.compile_to_descriptor(DescriptorType::Wsh),
+            Err(CompilerError::UncompressedKeyInSegwit)
+        );
+        match policy.compile_to_cheapest_descriptor().unwrap().0 {
+            Descriptor::Sh(..) => {}
+            desc => panic!("unexpected descriptor {}", desc),
+        }
+    }
+}
@@ -22,10 +22,13 @@
 //! these with BIP32 paths, pay-to-contract instructions, etc.
 //!
 
+pub mod bolt3;
+pub mod builder;
 #[cfg(feature = "compiler")]
 pub mod compiler;
 pub mod concrete;
 pub mod semantic;
+pub mod templates;
 
 use descriptor::Descriptor;
 use miniscript::Miniscript;
@@ -77,8 +80,8 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Terminal<Pk> {
                 Semantic::And(vec![left.node.lift(), right.node.lift()])
             }
             Terminal::AndOr(ref a, ref b, ref c) => Semantic::Or(vec![
-                Semantic::And(vec![a.node.lift(), c.node.lift()]),
-                b.node.lift(),
+                Semantic::And(vec![a.node.lift(), b.node.lift()]),
+                c.node.lift(),
             ]),
             Terminal::OrB(ref left, ref right)
             | Terminal::OrD(ref left, ref right)
@@ -121,6 +124,10 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Semantic<Pk> {
     }
 }
 
+/// Weighted thresholds are lifted to thresholds in which each subpolicy is
+/// repeated as many times as its reduced weight. Lifting requires a policy
+/// which was parsed or passed `Concrete::check_policy`, which bound the
+/// total weight; an unchecked one may need arbitrarily many copies.
 impl<Pk: MiniscriptKey> Liftable<Pk> for Concrete<Pk> {
     fn lift(&self) -> Semantic<Pk> {
         match *self {
@@ -138,7 +145,35 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Concrete<Pk> {
             Concrete::Threshold(k, ref subs) => {
                 Semantic::Threshold(k, subs.iter().map(Liftable::lift).collect())
             }
+            Concrete::WeightedThreshold(k, ref subs) => {
+                let (k, weights) = concrete::reduced_weights(k, subs);
+                Semantic::Threshold(
+                    k,
+                    subs.iter()
+                        .zip(weights)
+                        .flat_map(|(sub, weight)| {
+                            let lifted = sub.1.lift();
+                            (0..weight).map(move |_| lifted.clone())
+                        })
+                        .collect(),
+                )
+            }
         }
         .normalized()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn lift_andor() {
+        let ms = Miniscript::<String>::from_str("andor(c:pk(A),c:pk(B),c:pk(C))").unwrap();
+        assert_eq!(
+            ms.lift(),
+            Semantic::from_str("or(and(pkh(A),pkh(B)),pkh(C))").unwrap()
+        );
+    }
+}
@@ -15,13 +15,15 @@
 //! Abstract Policies
 
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use std::collections::HashSet;
 use std::{fmt, str};
 
 use errstr;
+use miniscript::satisfy;
 use std::str::FromStr;
 use Error;
-use {expression, MiniscriptKey};
+use {expression, AbsLockTime, MiniscriptKey, RelLockTime};
 
 /// Abstract policy which corresponds to the semantics of a Miniscript
 /// and which allows complex forms of analysis, e.g. filtering and
@@ -37,10 +39,10 @@ pub enum Policy<Pk: MiniscriptKey> {
     Trivial,
     /// Signature and public key matching a given hash is required
     KeyHash(Pk::Hash),
-    /// A relative locktime restriction
-    After(u32),
     /// An absolute locktime restriction
-    Older(u32),
+    After(AbsLockTime),
+    /// A relative locktime restriction
+    Older(RelLockTime),
     /// A SHA256 whose preimage must be provided to satisfy the descriptor
     Sha256(sha256::Hash),
     /// A SHA256d whose preimage must be provided to satisfy the descriptor
@@ -216,10 +218,14 @@ where
                 Pk::Hash::from_str(pk).map(Policy::KeyHash)
             }),
             ("after", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Policy::After)
+                expression::parse_num(x)
+                    .and_then(AbsLockTime::from_consensus)
+                    .map(Policy::After)
             }),
             ("older", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Policy::Older)
+                expression::parse_num(x)
+                    .and_then(RelLockTime::from_consensus)
+                    .map(Policy::Older)
             }),
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256::Hash::from_hex(x).map(Policy::Sha256)
@@ -276,7 +282,8 @@ where
 
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Flatten out trees of `And`s and `Or`s; eliminate `Trivial` and
-    /// `Unsatisfiable`s. Does not reorder any branches; use `.sort`.
+    /// `Unsatisfiable`s, and replace thresholds which are trivially `And`s
+    /// or `Or`s by those. Does not reorder any branches; use `.sort`.
     pub fn normalized(self) -> Policy<Pk> {
         match self {
             Policy::And(subs) => {
@@ -298,7 +305,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::Or(subs) => {
                 let mut ret_subs = Vec::with_capacity(subs.len());
                 for sub in subs {
-                    match sub {
+                    match sub.normalized() {
                         Policy::Trivial => return Policy::Trivial,
                         Policy::Unsatisfiable => {}
                         Policy::Or(or_subs) => ret_subs.extend(or_subs),
@@ -306,11 +313,33 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     }
                 }
                 match ret_subs.len() {
-                    0 => Policy::Trivial,
+                    0 => Policy::Unsatisfiable,
                     1 => ret_subs.pop().unwrap(),
                     _ => Policy::Or(ret_subs),
                 }
             }
+            Policy::Threshold(k, subs) => {
+                let mut k = k;
+                let mut ret_subs = Vec::with_capacity(subs.len());
+                for sub in subs {
+                    match sub.normalized() {
+                        Policy::Trivial => k = k.saturating_sub(1),
+                        Policy::Unsatisfiable => {}
+                        x => ret_subs.push(x),
+                    }
+                }
+                if k == 0 {
+                    Policy::Trivial
+                } else if k > ret_subs.len() {
+                    Policy::Unsatisfiable
+                } else if k == ret_subs.len() {
+                    Policy::And(ret_subs).normalized()
+                } else if k == 1 {
+                    Policy::Or(ret_subs).normalized()
+                } else {
+                    Policy::Threshold(k, ret_subs)
+                }
+            }
             x => x,
         }
     }
@@ -342,7 +371,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             | Policy::Ripemd160(..)
             | Policy::Hash160(..) => vec![],
             Policy::After(..) => vec![],
-            Policy::Older(t) => vec![t],
+            Policy::Older(t) => vec![t.to_consensus_u32()],
             Policy::And(ref subs) | Policy::Threshold(_, ref subs) => {
                 subs.iter().fold(vec![], |mut acc, x| {
                     acc.extend(x.real_relative_timelocks());
@@ -365,12 +394,47 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         ret
     }
 
+    /// Helper function to do the recursion in `absolute_timelocks`.
+    fn real_absolute_timelocks(&self) -> Vec<AbsLockTime> {
+        match *self {
+            Policy::Unsatisfiable
+            | Policy::Trivial
+            | Policy::KeyHash(..)
+            | Policy::Sha256(..)
+            | Policy::Hash256(..)
+            | Policy::Ripemd160(..)
+            | Policy::Hash160(..) => vec![],
+            Policy::Older(..) => vec![],
+            Policy::After(t) => vec![t],
+            Policy::And(ref subs) | Policy::Threshold(_, ref subs) => {
+                subs.iter().fold(vec![], |mut acc, x| {
+                    acc.extend(x.real_absolute_timelocks());
+                    acc
+                })
+            }
+            Policy::Or(ref subs) => subs.iter().fold(vec![], |mut acc, x| {
+                acc.extend(x.real_absolute_timelocks());
+                acc
+            }),
+        }
+    }
+
+    /// Returns a list of all absolute timelocks which appear in the policy.
+    /// Block heights sort before timestamps; use `AbsLockTime::is_block_height`
+    /// to tell them apart.
+    pub fn absolute_timelocks(&self) -> Vec<AbsLockTime> {
+        let mut ret = self.real_absolute_timelocks();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
     /// Filter a policy by eliminating relative timelock constraints
-    /// that are not satisfied at the given age.
+    /// that are not satisfied by an input with the given `nSequence`.
     pub fn at_age(mut self, time: u32) -> Policy<Pk> {
         self = match self {
             Policy::Older(t) => {
-                if t > time {
+                if !t.is_satisfied_by(time) {
                     Policy::Unsatisfiable
                 } else {
                     Policy::Older(t)
@@ -388,6 +452,180 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         self.normalized()
     }
 
+    /// Filter a policy by eliminating absolute timelock constraints
+    /// that are not satisfied by a transaction with the given `nLockTime`.
+    /// Heights and timestamps are never comparable, so a height eliminates
+    /// all timestamp-based constraints and vice-versa.
+    pub fn at_lock_time(mut self, n: u32) -> Policy<Pk> {
+        self = match self {
+            Policy::After(t) => {
+                if !t.is_satisfied_by(n) {
+                    Policy::Unsatisfiable
+                } else {
+                    Policy::After(t)
+                }
+            }
+            Policy::And(subs) => {
+                Policy::And(subs.into_iter().map(|sub| sub.at_lock_time(n)).collect())
+            }
+            Policy::Or(subs) => {
+                Policy::Or(subs.into_iter().map(|sub| sub.at_lock_time(n)).collect())
+            }
+            Policy::Threshold(k, subs) => {
+                Policy::Threshold(k, subs.into_iter().map(|sub| sub.at_lock_time(n)).collect())
+            }
+            x => x,
+        };
+        self.normalized()
+    }
+
+    /// Filter a policy by eliminating all timelock constraints that are not
+    /// satisfied by an input with the given `nSequence` in a transaction
+    /// with the given `nLockTime`.
+    pub fn at_age_and_lock_time(self, age: u32, lock_time: u32) -> Policy<Pk> {
+        self.at_age(age).at_lock_time(lock_time)
+    }
+
+    /// Filter a policy by replacing every key whose hash is not in `keys`,
+    /// and every hash lock whose preimage is not in `preimages`, by
+    /// `Unsatisfiable`. The result is normalized, so what remains are the
+    /// conditions (signatures, preimages and timelocks) under which the
+    /// available keys and preimages can spend.
+    pub fn with_available(self, keys: &HashSet<Pk::Hash>, preimages: &[[u8; 32]]) -> Policy<Pk> {
+        let available = |found: bool, policy: Policy<Pk>| {
+            if found {
+                policy
+            } else {
+                Policy::Unsatisfiable
+            }
+        };
+        match self {
+            Policy::KeyHash(pkh) => {
+                let found = keys.contains(&pkh);
+                available(found, Policy::KeyHash(pkh))
+            }
+            Policy::Sha256(h) => available(
+                preimages.iter().any(|p| sha256::Hash::hash(p) == h),
+                Policy::Sha256(h),
+            ),
+            Policy::Hash256(h) => available(
+                preimages.iter().any(|p| sha256d::Hash::hash(p) == h),
+                Policy::Hash256(h),
+            ),
+            Policy::Ripemd160(h) => available(
+                preimages.iter().any(|p| ripemd160::Hash::hash(p) == h),
+                Policy::Ripemd160(h),
+            ),
+            Policy::Hash160(h) => available(
+                preimages.iter().any(|p| hash160::Hash::hash(p) == h),
+                Policy::Hash160(h),
+            ),
+            Policy::And(subs) => Policy::And(
+                subs.into_iter()
+                    .map(|sub| sub.with_available(keys, preimages))
+                    .collect(),
+            ),
+            Policy::Or(subs) => Policy::Or(
+                subs.into_iter()
+                    .map(|sub| sub.with_available(keys, preimages))
+                    .collect(),
+            ),
+            Policy::Threshold(k, subs) => Policy::Threshold(
+                k,
+                subs.into_iter()
+                    .map(|sub| sub.with_available(keys, preimages))
+                    .collect(),
+            ),
+            x => x,
+        }
+        .normalized()
+    }
+
+    /// Helper function for `key_loss_sets` and `key_compromise_sets`.
+    /// Computes the minimal sets of keys which satisfy the policy, taking
+    /// hash locks as satisfied and timelocks as satisfied iff `timelocks`
+    /// is set. With `dual` set, instead computes the minimal sets of keys
+    /// without which the policy cannot be satisfied. Fails as soon as any
+    /// subpolicy would need more than `max_sets` sets.
+    fn key_sets(
+        &self,
+        timelocks: bool,
+        dual: bool,
+        max_sets: usize,
+    ) -> Result<Vec<Vec<Pk::Hash>>, Error> {
+        let constant = |satisfied: bool| {
+            if satisfied != dual {
+                vec![vec![]]
+            } else {
+                vec![]
+            }
+        };
+        let sets = match *self {
+            Policy::KeyHash(ref pkh) => vec![vec![pkh.clone()]],
+            Policy::Unsatisfiable => constant(false),
+            Policy::Trivial
+            | Policy::Sha256(..)
+            | Policy::Hash256(..)
+            | Policy::Ripemd160(..)
+            | Policy::Hash160(..) => constant(true),
+            Policy::After(..) | Policy::Older(..) => constant(timelocks),
+            Policy::And(ref subs) | Policy::Or(ref subs) | Policy::Threshold(_, ref subs) => {
+                // The keys needed to satisfy `k` of `n` subpolicies are
+                // those without which `n - k + 1` of them cannot be
+                let k = match *self {
+                    Policy::And(..) => subs.len(),
+                    Policy::Threshold(k, _) => k,
+                    _ => 1,
+                };
+                let k = if dual {
+                    (subs.len() + 1).saturating_sub(k)
+                } else {
+                    k
+                };
+                let sub_sets = subs
+                    .iter()
+                    .map(|sub| sub.key_sets(timelocks, dual, max_sets))
+                    .collect::<Result<Vec<_>, _>>()?;
+                satisfy::capped_thresh_sets(k, &sub_sets, max_sets)
+                    .ok_or(Error::DnfTooLarge(max_sets))?
+            }
+        };
+        if sets.len() > max_sets {
+            Err(Error::DnfTooLarge(max_sets))
+        } else {
+            Ok(sets)
+        }
+    }
+
+    /// Returns the minimal sets of keys whose loss makes the policy
+    /// unsatisfiable, smallest first, assuming all hash preimages are
+    /// available and all timelocks eventually expire. An empty result
+    /// means no loss of keys can lock the funds.
+    ///
+    /// The number of sets can grow exponentially with the size of the
+    /// policy, so this fails with `Error::DnfTooLarge` as soon as any
+    /// subpolicy would need more than `max_sets` sets. The sets cannot be
+    /// derived from the key counts of `n_keys` and `minimum_n_keys`, so
+    /// they are computed directly.
+    pub fn key_loss_sets(&self, max_sets: usize) -> Result<Vec<Vec<Pk::Hash>>, Error> {
+        self.key_sets(true, true, max_sets)
+    }
+
+    /// Returns the minimal sets of keys whose compromise lets an attacker
+    /// satisfy the policy, smallest first, assuming the attacker knows all
+    /// hash preimages. Spending paths with timelocks are only considered
+    /// if `wait_for_timelocks` is set, in which case the smallest set has
+    /// `minimum_n_keys` distinct elements. An empty set means the policy
+    /// can be satisfied without any key. Fails like `key_loss_sets` if
+    /// there are more than `max_sets` sets.
+    pub fn key_compromise_sets(
+        &self,
+        wait_for_timelocks: bool,
+        max_sets: usize,
+    ) -> Result<Vec<Vec<Pk::Hash>>, Error> {
+        self.key_sets(wait_for_timelocks, false, max_sets)
+    }
+
     /// Count the number of public keys and keyhashes referenced in a policy.
     /// Duplicate keys will be double-counted.
     pub fn n_keys(&self) -> usize {
@@ -457,12 +695,189 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     }
 }
 
+/// Maximum number of terms of the disjunctive normal form of a policy which
+/// will be computed when checking entailment
+const ENTAILMENT_MAX_TERMS: usize = 10_000;
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Helper function to compare atomic conditions: whether every
+    /// transaction which satisfies `self` also satisfies `other`
+    fn atom_implies(&self, other: &Policy<Pk>) -> bool {
+        match (self, other) {
+            (&Policy::After(a), &Policy::After(b)) => b.is_implied_by(a),
+            (&Policy::Older(a), &Policy::Older(b)) => b.is_implied_by(a),
+            (a, b) => a == b,
+        }
+    }
+
+    /// Helper function to evaluate the policy when exactly the atomic
+    /// conditions in `atoms`, and those they imply, are satisfied
+    fn is_satisfied_by_atoms(&self, atoms: &[Policy<Pk>]) -> bool {
+        match *self {
+            Policy::Unsatisfiable => false,
+            Policy::Trivial => true,
+            Policy::And(ref subs) => subs.iter().all(|sub| sub.is_satisfied_by_atoms(atoms)),
+            Policy::Or(ref subs) => subs.iter().any(|sub| sub.is_satisfied_by_atoms(atoms)),
+            Policy::Threshold(k, ref subs) => {
+                subs.iter()
+                    .filter(|sub| sub.is_satisfied_by_atoms(atoms))
+                    .count()
+                    >= k
+            }
+            ref atom => atoms.iter().any(|a| a.atom_implies(atom)),
+        }
+    }
+
+    /// Computes the minimal sets of conditions which satisfy the policy,
+    /// i.e. its disjunctive normal form. Each set is a list of atomic
+    /// policies (key hashes, hash preimages and timelocks) which together
+    /// satisfy the policy, and no set is implied by another one. Within a
+    /// set only the strongest timelock of each kind is kept, and sets which
+    /// no transaction can satisfy, because they mix heights and times, are
+    /// dropped. Sets are sorted by size.
+    ///
+    /// The number of sets can grow exponentially with the size of the
+    /// policy, so this fails with `Error::DnfTooLarge` as soon as the
+    /// expansion of any subpolicy would need more than `max_sets` sets.
+    pub fn minimal_satisfying_sets(&self, max_sets: usize) -> Result<Vec<Vec<Policy<Pk>>>, Error> {
+        let sets = match *self {
+            Policy::Unsatisfiable => vec![],
+            Policy::Trivial => vec![vec![]],
+            Policy::And(ref subs) | Policy::Or(ref subs) | Policy::Threshold(_, ref subs) => {
+                let k = match *self {
+                    Policy::And(..) => subs.len(),
+                    Policy::Threshold(k, _) => k,
+                    _ => 1,
+                };
+                let sub_sets = subs
+                    .iter()
+                    .map(|sub| sub.minimal_satisfying_sets(max_sets))
+                    .collect::<Result<Vec<_>, _>>()?;
+                thresh_terms(k, &sub_sets, max_sets)?
+            }
+            ref atom => vec![vec![atom.clone()]],
+        };
+        if sets.len() > max_sets {
+            Err(Error::DnfTooLarge(max_sets))
+        } else {
+            Ok(sets)
+        }
+    }
+
+    /// Rewrites the policy in disjunctive normal form, as an `or` of `and`s
+    /// of atomic policies, one for each of its `minimal_satisfying_sets`
+    pub fn to_dnf(&self, max_terms: usize) -> Result<Policy<Pk>, Error> {
+        let mut terms: Vec<Policy<Pk>> = self
+            .minimal_satisfying_sets(max_terms)?
+            .into_iter()
+            .map(|mut set| match set.len() {
+                0 => Policy::Trivial,
+                1 => set.pop().unwrap(),
+                _ => Policy::And(set),
+            })
+            .collect();
+        Ok(match terms.len() {
+            0 => Policy::Unsatisfiable,
+            1 => terms.pop().unwrap(),
+            _ => Policy::Or(terms),
+        })
+    }
+
+    /// Whether every way of satisfying `self` also satisfies `other`, i.e.
+    /// whether `self` is at least as strict as `other`. Keys and hashes are
+    /// compared exactly, timelocks by consensus rules, so e.g. `older(144)`
+    /// entails `older(100)` but no height-based lock entails a time-based
+    /// one. Fails if the policies are too large to compare.
+    pub fn entails(&self, other: &Policy<Pk>) -> Result<bool, Error> {
+        let terms = self.minimal_satisfying_sets(ENTAILMENT_MAX_TERMS)?;
+        Ok(terms.iter().all(|term| other.is_satisfied_by_atoms(term)))
+    }
+
+    /// Whether `self` and `other` are satisfied by exactly the same
+    /// witnesses and transactions. See `entails`.
+    pub fn is_equivalent(&self, other: &Policy<Pk>) -> Result<bool, Error> {
+        Ok(self.entails(other)? && other.entails(self)?)
+    }
+}
+
+/// Computes the minimal sets of conditions needed to satisfy `k` of the
+/// given subpolicies, failing if any intermediate expansion would need more
+/// than `max_terms` sets
+fn thresh_terms<Pk: MiniscriptKey>(
+    k: usize,
+    subs: &[Vec<Vec<Policy<Pk>>>],
+    max_terms: usize,
+) -> Result<Vec<Vec<Policy<Pk>>>, Error> {
+    // `table[j]` holds the sets needed to satisfy `j` of the subpolicies
+    // considered so far
+    let mut table = vec![vec![]; k + 1];
+    table[0] = vec![vec![]];
+    for sub in subs {
+        for j in (1..k + 1).rev() {
+            if table[j - 1].len().saturating_mul(sub.len()) > max_terms {
+                return Err(Error::DnfTooLarge(max_terms));
+            }
+            let mut new: Vec<_> = satisfy::and_sets(&table[j - 1], sub)
+                .into_iter()
+                .filter_map(simplify_term)
+                .collect();
+            new.append(&mut table[j]);
+            table[j] = minimize_terms(new);
+        }
+    }
+    Ok(table.pop().unwrap())
+}
+
+/// Removes timelocks which are implied by other timelocks from a set of
+/// atomic conditions, or returns `None` if the set mixes heights and times
+fn simplify_term<Pk: MiniscriptKey>(term: Vec<Policy<Pk>>) -> Option<Vec<Policy<Pk>>> {
+    let mut ret: Vec<Policy<Pk>> = Vec::with_capacity(term.len());
+    for atom in term {
+        for kept in &ret {
+            match (&atom, kept) {
+                (&Policy::After(a), &Policy::After(b)) if !a.is_same_unit(b) => return None,
+                (&Policy::Older(a), &Policy::Older(b)) if !a.is_same_unit(b) => return None,
+                _ => {}
+            }
+        }
+        if ret.iter().any(|kept| kept.atom_implies(&atom)) {
+            continue;
+        }
+        ret.retain(|kept| !atom.atom_implies(kept));
+        ret.push(atom);
+    }
+    ret.sort();
+    Some(ret)
+}
+
+/// Drops sets of atomic conditions which imply other sets, and sorts the
+/// remainder by size
+fn minimize_terms<Pk: MiniscriptKey>(mut terms: Vec<Vec<Policy<Pk>>>) -> Vec<Vec<Policy<Pk>>> {
+    terms.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    terms.dedup();
+
+    let mut ret: Vec<Vec<Policy<Pk>>> = Vec::with_capacity(terms.len());
+    for term in terms {
+        let redundant = ret.iter().any(|kept| {
+            kept.iter()
+                .all(|needed| term.iter().any(|atom| atom.atom_implies(needed)))
+        });
+        if !redundant {
+            ret.push(term);
+        }
+    }
+    ret
+}
+
 #[cfg(test)]
 mod tests {
+    use bitcoin::secp256k1;
     use bitcoin::PublicKey;
     use std::str::FromStr;
 
     use super::*;
+    use policy::Liftable;
+    use Descriptor;
 
     type StringPolicy = Policy<String>;
 
@@ -495,7 +910,10 @@ mod tests {
         assert_eq!(policy.minimum_n_keys(), 1);
 
         let policy = StringPolicy::from_str("older(1000)").unwrap();
-        assert_eq!(policy, Policy::Older(1000));
+        assert_eq!(
+            policy,
+            Policy::Older(RelLockTime::from_consensus(1000).unwrap())
+        );
         assert_eq!(policy.relative_timelocks(), vec![1000]);
         assert_eq!(policy.clone().at_age(0), Policy::Unsatisfiable);
         assert_eq!(policy.clone().at_age(999), Policy::Unsatisfiable);
@@ -507,7 +925,10 @@ mod tests {
         let policy = StringPolicy::from_str("or(pkh(),older(1000))").unwrap();
         assert_eq!(
             policy,
-            Policy::Or(vec![Policy::KeyHash("".to_owned()), Policy::Older(1000),])
+            Policy::Or(vec![
+                Policy::KeyHash("".to_owned()),
+                Policy::Older(RelLockTime::from_consensus(1000).unwrap()),
+            ])
         );
         assert_eq!(policy.relative_timelocks(), vec![1000]);
         assert_eq!(policy.clone().at_age(0), Policy::KeyHash("".to_owned()));
@@ -528,11 +949,11 @@ mod tests {
             Policy::Threshold(
                 2,
                 vec![
-                    Policy::Older(1000),
-                    Policy::Older(10000),
-                    Policy::Older(1000),
-                    Policy::Older(2000),
-                    Policy::Older(2000),
+                    Policy::Older(RelLockTime::from_consensus(1000).unwrap()),
+                    Policy::Older(RelLockTime::from_consensus(10000).unwrap()),
+                    Policy::Older(RelLockTime::from_consensus(1000).unwrap()),
+                    Policy::Older(RelLockTime::from_consensus(2000).unwrap()),
+                    Policy::Older(RelLockTime::from_consensus(2000).unwrap()),
                 ]
             )
         );
@@ -540,5 +961,298 @@ mod tests {
             policy.relative_timelocks(),
             vec![1000, 2000, 10000] //sorted and dedup'd
         );
+
+        let policy = StringPolicy::from_str("after(1000)").unwrap();
+        assert_eq!(
+            policy,
+            Policy::After(AbsLockTime::from_consensus(1000).unwrap())
+        );
+        assert_eq!(
+            policy.absolute_timelocks(),
+            vec![AbsLockTime::from_consensus(1000).unwrap()]
+        );
+        assert_eq!(policy.relative_timelocks(), vec![]);
+        assert_eq!(policy.clone().at_lock_time(0), Policy::Unsatisfiable);
+        assert_eq!(policy.clone().at_lock_time(999), Policy::Unsatisfiable);
+        assert_eq!(policy.clone().at_lock_time(1000), policy.clone());
+        assert_eq!(policy.clone().at_lock_time(10000), policy.clone());
+        // A timestamp does not satisfy a height-based lock
+        assert_eq!(
+            policy.clone().at_lock_time(500_000_000),
+            Policy::Unsatisfiable
+        );
+
+        let policy = StringPolicy::from_str(
+            "or(and(pkh(),older(144),after(500000000)),after(600000),after(500000000))",
+        )
+        .unwrap();
+        let timelocks = policy.absolute_timelocks();
+        assert_eq!(
+            timelocks,
+            vec![
+                AbsLockTime::from_consensus(600000).unwrap(),
+                AbsLockTime::from_consensus(500000000).unwrap(),
+            ] // heights before times, dedup'd
+        );
+        assert!(timelocks[0].is_block_height());
+        assert!(timelocks[1].is_block_time());
+        assert_eq!(policy.relative_timelocks(), vec![144]);
+        assert_eq!(
+            policy.clone().at_age_and_lock_time(0, 0),
+            Policy::Unsatisfiable
+        );
+        assert_eq!(
+            policy.clone().at_age_and_lock_time(144, 0),
+            Policy::Unsatisfiable
+        );
+        assert_eq!(
+            policy.clone().at_age_and_lock_time(144, 600000),
+            StringPolicy::from_str("after(600000)").unwrap()
+        );
+        assert_eq!(
+            policy.clone().at_age_and_lock_time(0, 500000000),
+            StringPolicy::from_str("after(500000000)").unwrap()
+        );
+        assert_eq!(
+            policy.clone().at_age_and_lock_time(144, 500000000),
+            StringPolicy::from_str("or(and(pkh(),older(144),after(500000000)),after(500000000))")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn dnf() {
+        let policy = StringPolicy::from_str(
+            "or(and(pkh(A),pkh(B)),thresh(2,pkh(A),pkh(B),older(100)),and(pkh(C),older(200),older(300)))",
+        )
+        .unwrap();
+        let sets = policy.minimal_satisfying_sets(10).unwrap();
+        assert_eq!(
+            sets,
+            vec![
+                vec![
+                    Policy::KeyHash("A".to_owned()),
+                    Policy::KeyHash("B".to_owned())
+                ],
+                vec![
+                    Policy::KeyHash("A".to_owned()),
+                    Policy::Older(RelLockTime::from_consensus(100).unwrap()),
+                ],
+                vec![
+                    Policy::KeyHash("B".to_owned()),
+                    Policy::Older(RelLockTime::from_consensus(100).unwrap()),
+                ],
+                vec![
+                    Policy::KeyHash("C".to_owned()),
+                    Policy::Older(RelLockTime::from_consensus(300).unwrap()),
+                ],
+            ]
+        );
+        assert_eq!(
+            policy.to_dnf(10).unwrap(),
+            StringPolicy::from_str(
+                "or(and(pkh(A),pkh(B)),and(pkh(A),older(100)),and(pkh(B),older(100)),and(pkh(C),older(300)))"
+            )
+            .unwrap()
+        );
+        assert!(policy.to_dnf(10).unwrap().is_equivalent(&policy).unwrap());
+        assert_eq!(
+            StringPolicy::from_str("and(after(600000),after(500000001))")
+                .unwrap()
+                .to_dnf(10)
+                .unwrap(),
+            Policy::Unsatisfiable
+        );
+
+        // 10-of-20 has 184756 minimal sets
+        let big = StringPolicy::from_str(&format!(
+            "thresh(10,{})",
+            (0..20)
+                .map(|i| format!("pkh(K{})", i))
+                .collect::<Vec<_>>()
+                .join(",")
+        ))
+        .unwrap();
+        match big.minimal_satisfying_sets(1000) {
+            Err(Error::DnfTooLarge(1000)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn entailment() {
+        let pol = |s: &str| StringPolicy::from_str(s).unwrap();
+        let entails = |a: &str, b: &str| pol(a).entails(&pol(b)).unwrap();
+        let equivalent = |a: &str, b: &str| pol(a).is_equivalent(&pol(b)).unwrap();
+
+        assert!(entails("and(pkh(A),pkh(B))", "or(pkh(A),pkh(C))"));
+        assert!(!entails("or(pkh(A),pkh(C))", "and(pkh(A),pkh(B))"));
+        assert!(equivalent(
+            "thresh(2,pkh(A),pkh(B),pkh(C))",
+            "or(and(pkh(A),pkh(B)),and(pkh(A),pkh(C)),and(pkh(C),pkh(B)))"
+        ));
+        assert!(entails(
+            "thresh(3,pkh(A),pkh(B),pkh(C))",
+            "thresh(2,pkh(A),pkh(B),pkh(C))"
+        ));
+        assert!(!equivalent(
+            "thresh(2,pkh(A),pkh(B),pkh(C))",
+            "or(and(pkh(A),pkh(B)),and(pkh(A),pkh(C)))"
+        ));
+
+        // Timelocks are ordered, but only within the same unit
+        assert!(entails("older(2000)", "older(1000)"));
+        assert!(!entails("older(1000)", "older(2000)"));
+        assert!(!entails("older(4195304)", "older(1000)"));
+        assert!(entails("after(600000)", "after(500000)"));
+        assert!(!entails("after(600000)", "after(500000001)"));
+        assert!(equivalent(
+            "or(and(pkh(A),older(100)),and(pkh(A),older(200)))",
+            "and(pkh(A),older(100))"
+        ));
+        // Nothing satisfies both a height and a time
+        assert!(pol("and(after(600000),after(500000001))")
+            .entails(&Policy::Unsatisfiable)
+            .unwrap());
+
+        // Hash locks are only equal to themselves
+        let h1 = "1111111111111111111111111111111111111111111111111111111111111111";
+        let h2 = "2222222222222222222222222222222222222222222222222222222222222222";
+        assert!(entails(
+            &format!("and(pkh(A),sha256({}))", h1),
+            &format!("sha256({})", h1)
+        ));
+        assert!(!entails(
+            &format!("sha256({})", h1),
+            &format!("sha256({})", h2)
+        ));
+    }
+
+    #[test]
+    fn entailment_lifted() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let keys: Vec<PublicKey> = (1..4)
+            .map(|i| PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(
+                    &secp,
+                    &secp256k1::SecretKey::from_slice(&[i; 32]).unwrap(),
+                ),
+                compressed: true,
+            })
+            .collect();
+
+        let old: Descriptor<PublicKey> = Descriptor::from_str(&format!(
+            "wsh(thresh_m(2,{},{},{}))",
+            keys[0], keys[1], keys[2]
+        ))
+        .unwrap();
+        let new: Descriptor<PublicKey> = Descriptor::from_str(&format!(
+            "wsh(thresh(2,c:pk({}),sc:pk({}),sc:pk({})))",
+            keys[2], keys[0], keys[1]
+        ))
+        .unwrap();
+        let stricter: Descriptor<PublicKey> =
+            Descriptor::from_str(&format!("wsh(and_v(vc:pk({}),c:pk({})))", keys[0], keys[1]))
+                .unwrap();
+
+        assert!(old.lift().is_equivalent(&new.lift()).unwrap());
+        assert!(stricter.lift().entails(&old.lift()).unwrap());
+        assert!(!old.lift().entails(&stricter.lift()).unwrap());
+    }
+
+    #[test]
+    fn key_sets() {
+        let keys = |sets: &[&[&str]]| {
+            sets.iter()
+                .map(|set| set.iter().map(|s| s.to_string()).collect())
+                .collect::<Vec<Vec<_>>>()
+        };
+
+        // A vault: 2-of-3 hot keys, or a cold key after a delay
+        let policy =
+            StringPolicy::from_str("or(thresh(2,pkh(A),pkh(B),pkh(C)),and(pkh(D),older(1000)))")
+                .unwrap();
+        assert_eq!(
+            policy.key_loss_sets(100).unwrap(),
+            keys(&[&["A", "B", "D"], &["A", "C", "D"], &["B", "C", "D"]])
+        );
+        assert_eq!(
+            policy.key_compromise_sets(true, 100).unwrap(),
+            keys(&[&["D"], &["A", "B"], &["A", "C"], &["B", "C"]])
+        );
+        assert_eq!(
+            policy.key_compromise_sets(false, 100).unwrap(),
+            keys(&[&["A", "B"], &["A", "C"], &["B", "C"]])
+        );
+        assert_eq!(
+            policy.key_compromise_sets(true, 100).unwrap()[0].len(),
+            policy.minimum_n_keys()
+        );
+
+        // A single key is a single point of failure
+        let policy = StringPolicy::from_str(
+            "and(pkh(A),or(pkh(B),sha256(\
+             1111111111111111111111111111111111111111111111111111111111111111\
+             )))",
+        )
+        .unwrap();
+        assert_eq!(policy.key_loss_sets(100).unwrap(), keys(&[&["A"]]));
+        assert_eq!(
+            policy.key_compromise_sets(false, 100).unwrap(),
+            keys(&[&["A"]])
+        );
+
+        // Nothing can be lost from a policy without keys
+        let policy = StringPolicy::from_str("after(1000)").unwrap();
+        assert_eq!(policy.key_loss_sets(100).unwrap(), keys(&[]));
+        assert_eq!(policy.key_compromise_sets(true, 100).unwrap(), keys(&[&[]]));
+        assert_eq!(policy.key_compromise_sets(false, 100).unwrap(), keys(&[]));
+
+        // Large thresholds have too many sets to list
+        let subs: Vec<String> = (0..20).map(|i| format!("pkh(K{})", i)).collect();
+        let policy = StringPolicy::from_str(&format!("thresh(10,{})", subs.join(","))).unwrap();
+        match policy.key_loss_sets(1000) {
+            Err(Error::DnfTooLarge(1000)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match policy.key_compromise_sets(true, 1000) {
+            Err(Error::DnfTooLarge(1000)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        let policy = StringPolicy::from_str(&format!("thresh(19,{})", subs.join(","))).unwrap();
+        assert_eq!(policy.key_loss_sets(1000).unwrap().len(), 190);
+    }
+
+    #[test]
+    fn with_available() {
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let policy = StringPolicy::from_str(&format!(
+            "or(thresh(2,pkh(A),pkh(B),pkh(C)),and(pkh(D),sha256({})),and(pkh(A),older(1000)))",
+            hash
+        ))
+        .unwrap();
+
+        let keys = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<HashSet<_>>();
+
+        // With A and C, the threshold can be met directly
+        assert_eq!(
+            policy.clone().with_available(&keys(&["A", "C"]), &[]),
+            StringPolicy::from_str("or(and(pkh(A),pkh(C)),and(pkh(A),older(1000)))").unwrap()
+        );
+        // With A alone, only the timelocked branch remains
+        assert_eq!(
+            policy.clone().with_available(&keys(&["A"]), &[]),
+            StringPolicy::from_str("and(pkh(A),older(1000))").unwrap()
+        );
+        // D needs the preimage
+        assert_eq!(
+            policy.clone().with_available(&keys(&["D"]), &[]),
+            Policy::Unsatisfiable
+        );
+        assert_eq!(
+            policy.clone().with_available(&keys(&["D"]), &[preimage]),
+            StringPolicy::from_str(&format!("and(pkh(D),sha256({}))", hash)).unwrap()
+        );
     }
 }
@@ -0,0 +1,259 @@
+// Miniscript
+// Written in 2020 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Policy Templates
+//!
+//! Constructors for concrete policies of commonly used contracts. Each
+//! documents its spending paths, and the relative probabilities it assigns
+//! to them for the compiler. Timelocks are given in their consensus
+//! encoding, as for `older` and `after` policies, and every result is
+//! checked with `Concrete::check_policy`, so that invalid timelocks or
+//! reused keys are reported as errors.
+//!
+
+use bitcoin::hashes::sha256;
+
+use policy::builder::{self, after, older, pk};
+use policy::concrete::PolicyError;
+use policy::Concrete;
+use {Error, MiniscriptKey};
+
+/// Hashed timelock contract, as used in payment channels
+///
+/// Spending paths:
+/// * `redeem` signs and reveals the preimage of `hash`
+/// * `refund` signs once the output is `expiry` blocks (or 512-second
+///   intervals) old
+///
+/// Redeeming is assumed 10 times as likely as a refund.
+pub fn htlc<Pk: MiniscriptKey>(
+    hash: sha256::Hash,
+    redeem: Pk,
+    refund: Pk,
+    expiry: u32,
+) -> Result<Concrete<Pk>, Error> {
+    builder::or(vec![
+        (10, builder::sha256(hash).and(pk(redeem))),
+        (1, older(expiry).and(pk(refund))),
+    ])
+    .build()
+}
+
+/// One leg of a cross-chain atomic swap
+///
+/// Spending paths:
+/// * `participant` signs and reveals the preimage of `hash`, which lets
+///   the other party claim the other leg
+/// * `initiator` signs after the absolute block height or timestamp
+///   `timeout`
+///
+/// The same `hash` is used on both legs, and the leg funded by whoever
+/// chose the preimage should time out later, so that the other party has
+/// time to claim after the preimage is revealed. Claiming is assumed 10
+/// times as likely as a refund.
+pub fn atomic_swap<Pk: MiniscriptKey>(
+    hash: sha256::Hash,
+    participant: Pk,
+    initiator: Pk,
+    timeout: u32,
+) -> Result<Concrete<Pk>, Error> {
+    builder::or(vec![
+        (10, builder::sha256(hash).and(pk(participant))),
+        (1, after(timeout).and(pk(initiator))),
+    ])
+    .build()
+}
+
+/// Multisig whose threshold decays over time
+///
+/// Spending paths:
+/// * `k` of `keys` sign
+/// * `k - i` of `keys` sign once the output is as old as the `i`-th
+///   smallest relative locktime in `decay`
+///
+/// `decay` must have fewer than `k` entries, so that at least one
+/// signature is always needed; otherwise the threshold would decay to zero
+/// and this fails with `IncorrectThresh(0, n)`, for `n` keys. All paths
+/// are considered equally likely.
+pub fn decaying_multisig<Pk: MiniscriptKey>(
+    k: usize,
+    keys: Vec<Pk>,
+    decay: Vec<u32>,
+) -> Result<Concrete<Pk>, Error> {
+    if k == 0 || k > keys.len() {
+        return Err(Error::PolicyError(PolicyError::IncorrectThresh(
+            k,
+            keys.len(),
+        )));
+    }
+    if decay.len() >= k {
+        return Err(Error::PolicyError(PolicyError::IncorrectThresh(
+            0,
+            keys.len(),
+        )));
+    }
+    let subs = keys
+        .into_iter()
+        .map(pk)
+        .chain(decay.into_iter().map(older))
+        .collect();
+    builder::thresh(k, subs).build()
+}
+
+/// Key with a delayed recovery key, for lost key recovery
+///
+/// Spending paths:
+/// * `owner` signs
+/// * `recovery` signs once the output is `delay` blocks (or 512-second
+///   intervals) old
+///
+/// The owner is expected to move the coins before `delay` passes, which
+/// resets the clock, and to use their key 9 times out of 10.
+pub fn recovery<Pk: MiniscriptKey>(
+    owner: Pk,
+    recovery: Pk,
+    delay: u32,
+) -> Result<Concrete<Pk>, Error> {
+    builder::or(vec![(9, pk(owner)), (1, older(delay).and(pk(recovery)))]).build()
+}
+
+/// Inheritance of coins by a threshold of heirs after a fixed date
+///
+/// Spending paths:
+/// * `owner` signs
+/// * `k` of `heirs` sign after the absolute block height or timestamp
+///   `date`
+///
+/// The owner is expected to use their key 9 times out of 10.
+pub fn inheritance<Pk: MiniscriptKey>(
+    owner: Pk,
+    k: usize,
+    heirs: Vec<Pk>,
+    date: u32,
+) -> Result<Concrete<Pk>, Error> {
+    let mut heirs: Vec<_> = heirs.into_iter().map(pk).collect();
+    let heirs = if k == 1 && heirs.len() == 1 {
+        heirs.pop().unwrap()
+    } else {
+        builder::thresh(k, heirs)
+    };
+    builder::or(vec![(9, pk(owner)), (1, after(date).and(heirs))]).build()
+}
+
+/// Unvault output of a two-stage vault
+///
+/// Coins are held in a vault output, for example a multisig of cold keys,
+/// and can only be spent by first moving them to this output, which
+/// announces the spend. Spending paths:
+/// * `hot` signs once the output is `delay` blocks (or 512-second
+///   intervals) old, completing the spend
+/// * `cold` signs at any time, cancelling the spend, for example by moving
+///   the coins back to a vault output
+///
+/// The delay gives watchtowers time to cancel an unauthorized spend. The
+/// spend is assumed to complete 9 times out of 10.
+pub fn unvault<Pk: MiniscriptKey>(hot: Pk, cold: Pk, delay: u32) -> Result<Concrete<Pk>, Error> {
+    builder::or(vec![(9, older(delay).and(pk(hot))), (1, pk(cold))]).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use policy::Liftable;
+
+    fn keys(names: &str) -> Vec<String> {
+        names.chars().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn templates() {
+        let hash = sha256::Hash::hash(&[]);
+        let policy = htlc(hash, "A".to_owned(), "B".to_owned(), 144).unwrap();
+        assert_eq!(
+            policy.to_string(),
+            format!("or(10@and(sha256({}),pk(A)),1@and(older(144),pk(B)))", hash)
+        );
+        let policy = atomic_swap(hash, "A".to_owned(), "B".to_owned(), 600000).unwrap();
+        assert_eq!(
+            policy.to_string(),
+            format!(
+                "or(10@and(sha256({}),pk(A)),1@and(after(600000),pk(B)))",
+                hash
+            )
+        );
+
+        let policy = decaying_multisig(3, keys("ABC"), vec![1000, 2000]).unwrap();
+        assert_eq!(
+            policy.to_string(),
+            "thresh(3,pk(A),pk(B),pk(C),older(1000),older(2000))"
+        );
+        assert_eq!(policy.lift().at_age(999).minimum_n_keys(), 3);
+        assert_eq!(policy.lift().at_age(1000).minimum_n_keys(), 2);
+        assert_eq!(policy.lift().at_age(2000).minimum_n_keys(), 1);
+
+        let policy = recovery("A".to_owned(), "B".to_owned(), 52560).unwrap();
+        assert_eq!(policy.to_string(), "or(9@pk(A),1@and(older(52560),pk(B)))");
+
+        let policy = inheritance("A".to_owned(), 2, keys("BCD"), 1700000000).unwrap();
+        assert_eq!(
+            policy.to_string(),
+            "or(9@pk(A),1@and(after(1700000000),thresh(2,pk(B),pk(C),pk(D))))"
+        );
+        let policy = inheritance("A".to_owned(), 1, keys("B"), 700000).unwrap();
+        assert_eq!(policy.to_string(), "or(9@pk(A),1@and(after(700000),pk(B)))");
+
+        let policy = unvault("A".to_owned(), "B".to_owned(), 6).unwrap();
+        assert_eq!(policy.to_string(), "or(9@and(older(6),pk(A)),1@pk(B))");
+    }
+
+    #[test]
+    fn template_errors() {
+        let hash = sha256::Hash::hash(&[]);
+        match htlc(hash, "A".to_owned(), "A".to_owned(), 144) {
+            Err(Error::PolicyError(PolicyError::DuplicatePubKeys)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match decaying_multisig(4, keys("ABC"), vec![1000]) {
+            Err(Error::PolicyError(PolicyError::IncorrectThresh(4, 3))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match decaying_multisig(2, keys("ABC"), vec![1000, 2000]) {
+            Err(Error::PolicyError(PolicyError::IncorrectThresh(0, 3))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match unvault("A".to_owned(), "B".to_owned(), 0) {
+            Err(Error::InvalidRelativeLockTime(0)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn compile_templates() {
+        let hash = sha256::Hash::hash(&[]);
+        let policies = vec![
+            htlc(hash, "A".to_owned(), "B".to_owned(), 144).unwrap(),
+            atomic_swap(hash, "A".to_owned(), "B".to_owned(), 600000).unwrap(),
+            decaying_multisig(3, keys("ABC"), vec![1000, 2000]).unwrap(),
+            recovery("A".to_owned(), "B".to_owned(), 52560).unwrap(),
+            inheritance("A".to_owned(), 2, keys("BCD"), 1700000000).unwrap(),
+            unvault("A".to_owned(), "B".to_owned(), 6).unwrap(),
+        ];
+        for policy in policies {
+            let ms = policy.compile().unwrap();
+            assert!(ms.lift().is_equivalent(&policy.lift()).unwrap());
+        }
+    }
+}